@@ -44,14 +44,28 @@ impl<'a> ToTokens for Pin<'a> {
                 /// Configures the pin to use peripheral A
                 /// AB Select -- select A.  Datasheet §31.5.3
                 pub fn into_peripheral_a(self, absr: &mut ABSR) -> #pin_ident<PeripheralA> {
-                    absr.absr().write(|w| w.#accessor().clear_bit());
+                    absr.absr().modify(|_, w| w.#accessor().clear_bit());
+                    // Hand the line over to the peripheral.  Datasheet §31.5.2
+                    unsafe { (*#upper_name::ptr()).pdr.write_with_zero(|w| w.#accessor().set_bit()) };
                     #pin_ident { _mode: PhantomData }
                 }
 
                 /// Configures the pin to use peripheral B
                 /// AB Select -- select B.  Datasheet §31.5.3
                 pub fn into_peripheral_b(self, absr: &mut ABSR) -> #pin_ident<PeripheralB> {
-                    absr.absr().write(|w| w.#accessor().set_bit());
+                    absr.absr().modify(|_, w| w.#accessor().set_bit());
+                    // Hand the line over to the peripheral.  Datasheet §31.5.2
+                    unsafe { (*#upper_name::ptr()).pdr.write_with_zero(|w| w.#accessor().set_bit()) };
+                    #pin_ident { _mode: PhantomData }
+                }
+
+                /// Configures the pin as an analog input.  The ADC or DACC takes
+                /// the line over once its channel is enabled.
+                pub fn into_analog(self) -> #pin_ident<Analog> {
+                    let pio = unsafe { &*#upper_name::ptr() };
+                    pio.odr.write_with_zero(|w| w.#accessor().set_bit());
+                    // The pull-up would load the analog source
+                    pio.pudr.write_with_zero(|w| w.#accessor().set_bit());
                     #pin_ident { _mode: PhantomData }
                 }
 
@@ -127,7 +127,7 @@ impl<'a> ToTokens for Pio {
         tokens.extend(quote!(
             pub mod #lower_name {
                 use super::{
-                    Floating, Gpio, GpioExt, Input, OpenDrain, Output, PXx, PullDown, PullUp, PushPull, PeripheralA,
+                    Analog, Floating, Gpio, GpioExt, Input, OpenDrain, Output, PXx, PullDown, PullUp, PushPull, PeripheralA,
                     PeripheralB,
                 };
 
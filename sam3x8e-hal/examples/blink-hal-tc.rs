@@ -42,7 +42,8 @@ fn main() -> ! {
     // Enable TC Timer 4 clock
     pmc.enable_clock(PeripheralClock::Tc4);
 
-    let mut delay = Delay::<TimerCounter4>::new( TimerCounter4(p.TC1), pmc.clocks );
+    let (_, tc4, _) = p.TC1.split();
+    let mut delay = Delay::<TimerCounter4>::new(tc4, pmc.clocks);
 
     let mut pioc = p.PIOC.split(&mut pmc);
     let mut blue = pioc
@@ -0,0 +1,666 @@
+/*
+ *    This file (src/adc.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Analog-to-Digital Converter
+//!
+//! A single 12-bit converter multiplexed over 16 channels: the 15 analog pins
+//! and the temperature sensor.  Pins are handed to the ADC in `Analog` mode,
+//! and converting one is a `read` through the pin:
+//!
+//! ```ignore
+//! let mut adc = p.ADC.adc(adc::Config::new(), &mut pmc);
+//! let mut a0 = pioa.pa16.into_analog();
+//! let value = adc.read(&mut a0);
+//! ```
+//!
+//! For sampling at a fixed rate the ADC is turned into a `Sequenced` one,
+//! converting a sequence of channels on each hardware trigger.  Results are
+//! tagged with their channel and can be streamed out by the PDC:
+//!
+//! ```ignore
+//! let sequence = Sequence::new().then(&a0).then(&a1);
+//! let sampler = adc.sequence(&sequence, Trigger::Tioa0);
+//! let mut stream = sampler.read_double(first, second);
+//! ```
+//!
+//! Each channel has its own gain and offset, and adjacent channels can be
+//! paired into a differential input.  The comparison window in `compare`
+//! flags results leaving or entering a range without the CPU looking at them.
+
+use crate::gpio::Analog;
+use crate::gpio::pioa::{PA2, PA3, PA4, PA6, PA16, PA22, PA23, PA24};
+use crate::gpio::piob::{PB12, PB13, PB17, PB18, PB19, PB20, PB21};
+#[cfg(feature = "unproven")]
+use crate::hal;
+use crate::latch::Latch;
+use crate::pac::adc::mr::{SETTLING_A, STARTUP_A, TRGSEL_A};
+use crate::pac::ADC;
+use crate::pdc;
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::pwm::compare::EventLine;
+use crate::time::{Hertz, U32Ext};
+
+pub mod compare;
+
+/// Fastest ADC clock
+const MAX_CLOCK: u32 = 22_000_000;
+/// Time the converter needs to power up from off
+const STARTUP_TIME_US: u32 = 40;
+/// Time the analog front end needs to settle after a gain or offset change
+const SETTLING_TIME_NS: u32 = 200;
+
+/// MR LOWRES, missing from the PAC
+const MR_LOWRES: u32 = 1 << 4;
+
+const ISR_DRDY: u32 = 1 << 24;
+const ISR_GOVRE: u32 = 1 << 25;
+const ISR_COMPE: u32 = 1 << 26;
+
+/// Longest conversion sequence, USCH1 to USCH15
+pub const MAX_SEQUENCE: usize = 15;
+
+/// ADC errors
+#[derive(Debug)]
+pub enum Error {
+    /// A conversion finished before the previous result was read
+    Overrun,
+}
+
+/// Conversion resolution
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Resolution {
+    Bits10,
+    Bits12,
+}
+
+impl Resolution {
+    /// Largest converted value
+    pub fn max_value(self) -> u16 {
+        match self {
+            Resolution::Bits10 => 0x3ff,
+            Resolution::Bits12 => 0xfff,
+        }
+    }
+}
+
+/// ADC configuration
+pub struct Config {
+    clock: Hertz,
+    resolution: Resolution,
+    tracking_time_ns: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            clock: 20.mhz().into(),
+            resolution: Resolution::Bits12,
+            tracking_time_ns: 500,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// ADC clock, at most 22 MHz.  A conversion takes 20 clocks plus the
+    /// tracking time.
+    pub fn clock<F: Into<Hertz>>(mut self, clock: F) -> Self {
+        self.clock = clock.into();
+        assert!(self.clock.0 > 0 && self.clock.0 <= MAX_CLOCK);
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// How long the sample and hold tracks the input before converting.
+    /// Sources with a high impedance need longer.
+    pub fn tracking_time_ns(mut self, tracking_time_ns: u32) -> Self {
+        self.tracking_time_ns = tracking_time_ns;
+        self
+    }
+}
+
+/// ADC clock periods covering `time`, in units of `per_second`, rounded up
+fn clock_periods(clock: u32, time: u32, per_second: u32) -> u32 {
+    (clock as u64 * time as u64).div_ceil(per_second as u64) as u32
+}
+
+/// MR timing fields for a config: PRESCAL, STARTUP, SETTLING and TRACKTIM
+fn timing(clocks: &Clocks, config: &Config) -> (u8, STARTUP_A, SETTLING_A, u8) {
+    let master_clk = clocks.master_clk().0;
+
+    // ADCClock = MCK / ((PRESCAL + 1) * 2), rounded down to stay in spec
+    let prescal = master_clk.div_ceil(2 * config.clock.0).max(1) - 1;
+    assert!(prescal <= 0xff, "ADC clock too slow");
+    let clock = master_clk / ((prescal + 1) * 2);
+
+    let startup = clock_periods(clock, STARTUP_TIME_US, 1_000_000);
+    let startup = [
+        (0, STARTUP_A::SUT0), (8, STARTUP_A::SUT8), (16, STARTUP_A::SUT16), (24, STARTUP_A::SUT24),
+        (64, STARTUP_A::SUT64), (80, STARTUP_A::SUT80), (96, STARTUP_A::SUT96), (112, STARTUP_A::SUT112),
+        (512, STARTUP_A::SUT512), (576, STARTUP_A::SUT576), (640, STARTUP_A::SUT640), (704, STARTUP_A::SUT704),
+        (768, STARTUP_A::SUT768), (832, STARTUP_A::SUT832), (896, STARTUP_A::SUT896), (960, STARTUP_A::SUT960),
+    ]
+        .iter()
+        .find(|&&(periods, _)| periods >= startup)
+        .map_or(STARTUP_A::SUT960, |&(_, sut)| sut);
+
+    let settling = clock_periods(clock, SETTLING_TIME_NS, 1_000_000_000);
+    let settling = [(3, SETTLING_A::AST3), (5, SETTLING_A::AST5), (9, SETTLING_A::AST9), (17, SETTLING_A::AST17)]
+        .iter()
+        .find(|&&(periods, _)| periods >= settling)
+        .map_or(SETTLING_A::AST17, |&(_, ast)| ast);
+
+    // Tracking time = (TRACKTIM + 1) ADCClock periods
+    let tracking = clock_periods(clock, config.tracking_time_ns, 1_000_000_000);
+    let tracktim = tracking.clamp(1, 16) - 1;
+
+    (prescal as u8, startup, settling, tracktim as u8)
+}
+
+/// An ADC input
+pub trait Channel {
+    /// Channel number, 0 to 15
+    const ID: u8;
+}
+
+/// An input converted on its own, against ground
+pub trait SingleEnded: Channel {}
+
+/// The internal temperature sensor, on channel 15.  Taken from
+/// `Adc::enable_temperature_sensor`.
+pub struct TemperatureSensor {
+    _private: (),
+}
+
+macro_rules! channels {
+    ($($INPUT:ty => $id:expr,)+) => {
+        $(
+            impl Channel for $INPUT {
+                const ID: u8 = $id;
+            }
+
+            impl SingleEnded for $INPUT {}
+
+            #[cfg(feature = "unproven")]
+            impl hal::adc::Channel<ADC> for $INPUT {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $id
+                }
+            }
+        )+
+    };
+}
+
+channels!(
+    PA2<Analog> => 0,
+    PA3<Analog> => 1,
+    PA4<Analog> => 2,
+    PA6<Analog> => 3,
+    PA22<Analog> => 4,
+    PA23<Analog> => 5,
+    PA24<Analog> => 6,
+    PA16<Analog> => 7,
+    PB12<Analog> => 8,
+    PB13<Analog> => 9,
+    PB17<Analog> => 10,
+    PB18<Analog> => 11,
+    PB19<Analog> => 12,
+    PB20<Analog> => 13,
+    PB21<Analog> => 14,
+    TemperatureSensor => 15,
+);
+
+/// Programmable gain amplifier setting for single ended inputs.  CGR
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gain {
+    One,
+    Two,
+    Four,
+}
+
+impl Gain {
+    /// GAINx field
+    fn bits(self) -> u32 {
+        match self {
+            Gain::One => 1,
+            Gain::Two => 2,
+            Gain::Four => 3,
+        }
+    }
+}
+
+/// Programmable gain amplifier setting for differential inputs.  CGR
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DifferentialGain {
+    Half,
+    One,
+    Two,
+}
+
+impl DifferentialGain {
+    /// GAINx field, which reads differently once DIFFx is set
+    fn bits(self) -> u32 {
+        match self {
+            DifferentialGain::Half => 0,
+            DifferentialGain::One => 1,
+            DifferentialGain::Two => 2,
+        }
+    }
+}
+
+/// Two adjacent inputs converted as one differential input, from
+/// `Adc::differential`
+pub struct Differential<P, N> {
+    positive: P,
+    negative: N,
+}
+
+impl<P: Channel, N: Channel> Channel for Differential<P, N> {
+    const ID: u8 = P::ID;
+}
+
+#[cfg(feature = "unproven")]
+impl<P, N> hal::adc::Channel<ADC> for Differential<P, N>
+where
+    P: hal::adc::Channel<ADC, ID = u8>,
+{
+    type ID = u8;
+
+    fn channel() -> u8 {
+        P::channel()
+    }
+}
+
+/// ISR flags cleared by reading ISR, kept until they're taken so polling for
+/// results doesn't lose them
+static STATUS: Latch = Latch::new(ISR_GOVRE | ISR_COMPE);
+
+fn status() -> u32 {
+    STATUS.update(unsafe { (*ADC::ptr()).isr.read().bits() })
+}
+
+fn take_status(mask: u32) -> u32 {
+    STATUS.take(unsafe { (*ADC::ptr()).isr.read().bits() }, mask)
+}
+
+/// Conversion start for a sequenced ADC.  TRGSEL
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trigger {
+    /// `Sequenced::start` only
+    Software,
+    /// Back to back, as fast as the ADC goes
+    FreeRun,
+    /// Rising edge on ADTRG, PA11 in peripheral B
+    External,
+    /// Rising edge on TIOA of timer counter 0 channel 0
+    Tioa0,
+    /// Rising edge on TIOA of timer counter 0 channel 1
+    Tioa1,
+    /// Rising edge on TIOA of timer counter 0 channel 2
+    Tioa2,
+    /// A pulse on a PWM event line, see `pwm::compare`
+    Pwm(EventLine),
+}
+
+/// The channels a sequenced ADC converts on each trigger, in order.  A
+/// channel can come up more than once.
+pub struct Sequence {
+    channels: [u8; MAX_SEQUENCE],
+    len: usize,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Sequence { channels: [0; MAX_SEQUENCE], len: 0 }
+    }
+
+    /// Appends `input` to the sequence
+    pub fn then<C: Channel>(mut self, _input: &C) -> Self {
+        assert!(self.len < MAX_SEQUENCE, "ADC sequence too long");
+        self.channels[self.len] = C::ID;
+        self.len += 1;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// USCHx fields packed into SEQR1 and SEQR2
+    fn registers(&self) -> (u32, u32) {
+        self.channels[..self.len]
+            .iter()
+            .enumerate()
+            .fold((0, 0), |(seqr1, seqr2), (slot, &channel)| match slot < 8 {
+                true => (seqr1 | (channel as u32) << (slot * 4), seqr2),
+                false => (seqr1, seqr2 | (channel as u32) << ((slot - 8) * 4)),
+            })
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A conversion result read from LCDR, tagged with its channel
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sample {
+    pub channel: u8,
+    pub value: u16,
+}
+
+impl Sample {
+    /// Splits a tagged LCDR value, as the PDC stores them
+    pub fn from_tagged(word: u16) -> Self {
+        Sample { channel: (word >> 12) as u8, value: word & 0xfff }
+    }
+}
+
+/// Sequenced ADC interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// DRDY: a result is waiting in LCDR
+    DataReady,
+    /// GOVRE: a result was overwritten before it was read
+    Overrun,
+    /// COMPE: a result matched the comparison window
+    CompareMatch,
+    /// ENDRX: the PDC's current buffer is full
+    EndOfTransfer,
+    /// RXBUFF: both PDC buffers are full
+    BufferFull,
+}
+
+impl Event {
+    /// IER / IDR bit
+    fn mask(self) -> u32 {
+        match self {
+            Event::DataReady => ISR_DRDY,
+            Event::Overrun => ISR_GOVRE,
+            Event::CompareMatch => ISR_COMPE,
+            Event::EndOfTransfer => 1 << 27,
+            Event::BufferFull => 1 << 28,
+        }
+    }
+}
+
+/// Analog-to-Digital Converter
+pub struct Adc {
+    adc: ADC,
+    resolution: Resolution,
+}
+
+pub trait AdcExt {
+    fn adc(self, config: Config, pmc: &mut Pmc) -> Adc;
+}
+
+impl AdcExt for ADC {
+    fn adc(self, config: Config, pmc: &mut Pmc) -> Adc {
+        pmc.enable_clock(PeripheralClock::Adc);
+        let (prescal, startup, settling, tracktim) = timing(&pmc.clocks, &config);
+
+        self.cr.write_with_zero(|w| w.swrst().set_bit());
+        self.chdr.write_with_zero(|w| unsafe { w.bits(0xffff) });
+        self.mr.write(|w| unsafe {
+            w.trgen().dis()
+                .prescal().bits(prescal)
+                .startup().variant(startup)
+                .settling().variant(settling)
+                .tracktim().bits(tracktim)
+        });
+
+        let mut adc = Adc { adc: self, resolution: config.resolution };
+        adc.set_resolution(config.resolution);
+        adc
+    }
+}
+
+impl Adc {
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.adc.mr.modify(|r, w| unsafe {
+            w.bits(match resolution {
+                Resolution::Bits10 => r.bits() | MR_LOWRES,
+                Resolution::Bits12 => r.bits() & !MR_LOWRES,
+            })
+        });
+        self.resolution = resolution;
+    }
+
+    /// Largest value a conversion returns at the current resolution
+    pub fn max_value(&self) -> u16 {
+        self.resolution.max_value()
+    }
+
+    /// Powers the temperature sensor up.  Give it some tens of microseconds
+    /// before the first conversion.
+    pub fn enable_temperature_sensor(&mut self) -> TemperatureSensor {
+        self.adc.acr.modify(|_, w| w.tson().set_bit());
+        TemperatureSensor { _private: () }
+    }
+
+    pub fn disable_temperature_sensor(&mut self, _sensor: TemperatureSensor) {
+        self.adc.acr.modify(|_, w| w.tson().clear_bit());
+    }
+
+    /// Pairs two inputs into a differential one.  The pairs are channels 0
+    /// and 1, 2 and 3 and so on, the even channel being the positive input.
+    /// Results are offset binary, half scale meaning 0 V across the pair.
+    pub fn differential<P: Channel, N: Channel>(&mut self, positive: P, negative: N) -> Differential<P, N> {
+        assert!(P::ID % 2 == 0 && N::ID == P::ID + 1, "not a differential pair");
+        let diff = 1 << (16 + P::ID as u32);
+
+        self.allow_analog_change();
+        self.adc.cor.modify(|r, w| unsafe { w.bits(r.bits() | diff) });
+        self.write_gain(P::ID, DifferentialGain::One.bits());
+
+        Differential { positive, negative }
+    }
+
+    /// Splits a differential input back into single ended ones
+    pub fn single_ended<P: Channel, N: Channel>(&mut self, input: Differential<P, N>) -> (P, N) {
+        let diff = 1 << (16 + P::ID as u32);
+
+        self.adc.cor.modify(|r, w| unsafe { w.bits(r.bits() & !diff) });
+        self.write_gain(P::ID, Gain::One.bits());
+
+        (input.positive, input.negative)
+    }
+
+    /// Sets the gain applied to `input` before conversion
+    pub fn set_gain<C: SingleEnded>(&mut self, _input: &C, gain: Gain) {
+        self.write_gain(C::ID, gain.bits());
+    }
+
+    /// Sets the gain applied to a differential `input` before conversion
+    pub fn set_differential_gain<P: Channel, N: Channel>(&mut self, _input: &Differential<P, N>, gain: DifferentialGain) {
+        self.write_gain(P::ID, gain.bits());
+    }
+
+    /// Writes the GAINx field of channel `id`
+    fn write_gain(&mut self, id: u8, bits: u32) {
+        let shift = id as u32 * 2;
+
+        self.allow_analog_change();
+        self.adc.cgr.modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | bits << shift) });
+    }
+
+    /// Centers the conversion range of `input` on VREF / 2 rather than on
+    /// half the gained range, for small signals riding on a mid supply bias
+    pub fn set_offset<C: Channel>(&mut self, _input: &C, offset: bool) {
+        let mask = 1 << C::ID as u32;
+
+        self.allow_analog_change();
+        self.adc.cor.modify(|r, w| unsafe {
+            w.bits(match offset {
+                true => r.bits() | mask,
+                false => r.bits() & !mask,
+            })
+        });
+    }
+
+    /// ANACH: lets gain, offset and differential settings differ between
+    /// channels, at the cost of the settling time on each change
+    fn allow_analog_change(&mut self) {
+        self.adc.mr.modify(|_, w| w.anach().allowed());
+    }
+
+    /// Converts `input` once, blocking until the result is in
+    pub fn read<C: Channel>(&mut self, _input: &mut C) -> u16 {
+        self.convert(C::ID)
+    }
+
+    fn convert(&mut self, id: u8) -> u16 {
+        let mask = 1 << id;
+
+        // Only the one channel, START converts every enabled channel
+        self.adc.chdr.write_with_zero(|w| unsafe { w.bits(0xffff & !mask) });
+        self.adc.cher.write_with_zero(|w| unsafe { w.bits(mask) });
+        self.adc.cr.write_with_zero(|w| w.start().set_bit());
+
+        while status() & mask == 0 {}
+
+        // Reading CDR clears EOC
+        let value = self.adc.cdr[id as usize].read().data().bits();
+        self.adc.chdr.write_with_zero(|w| unsafe { w.bits(mask) });
+        value
+    }
+
+    /// Converts `sequence` each time `trigger` fires
+    pub fn sequence(self, sequence: &Sequence, trigger: Trigger) -> Sequenced {
+        assert!(!sequence.is_empty());
+        let (seqr1, seqr2) = sequence.registers();
+
+        self.adc.chdr.write_with_zero(|w| unsafe { w.bits(0xffff) });
+        self.adc.seqr1.write(|w| unsafe { w.bits(seqr1) });
+        self.adc.seqr2.write(|w| unsafe { w.bits(seqr2) });
+        self.adc.emr.modify(|_, w| w.tag().set_bit());
+
+        let trgsel = match trigger {
+            Trigger::Software | Trigger::FreeRun | Trigger::External => TRGSEL_A::ADC_TRIG0,
+            Trigger::Tioa0 => TRGSEL_A::ADC_TRIG1,
+            Trigger::Tioa1 => TRGSEL_A::ADC_TRIG2,
+            Trigger::Tioa2 => TRGSEL_A::ADC_TRIG3,
+            Trigger::Pwm(EventLine::Line0) => TRGSEL_A::ADC_TRIG4,
+            Trigger::Pwm(EventLine::Line1) => TRGSEL_A::ADC_TRIG5,
+        };
+        let hardware = trigger != Trigger::Software && trigger != Trigger::FreeRun;
+        self.adc.mr.modify(|_, w| {
+            w.useq().reg_order()
+                .trgsel().variant(trgsel)
+                .trgen().bit(hardware)
+                .freerun().bit(trigger == Trigger::FreeRun)
+        });
+
+        // With USEQ, CHx enables sequence slot USCHx+1 rather than channel x
+        let slots = (1 << sequence.len()) - 1;
+        self.adc.cher.write_with_zero(|w| unsafe { w.bits(slots) });
+
+        STATUS.clear();
+        Sequenced { adc: self }
+    }
+
+    pub fn free(self) -> ADC {
+        self.adc.chdr.write_with_zero(|w| unsafe { w.bits(0xffff) });
+        self.adc
+    }
+}
+
+/// An ADC converting a `Sequence` on each trigger
+pub struct Sequenced {
+    adc: Adc,
+}
+
+impl Sequenced {
+    /// Starts a sequence by software, whatever the trigger
+    pub fn start(&mut self) {
+        self.adc.adc.cr.write_with_zero(|w| w.start().set_bit());
+    }
+
+    /// Reads the next result.  Fails once if results were lost since the last
+    /// read.
+    pub fn read(&mut self) -> nb::Result<Sample, Error> {
+        if take_status(ISR_GOVRE) != 0 {
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+        if status() & ISR_DRDY == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let lcdr = self.adc.adc.lcdr.read();
+        Ok(Sample { channel: lcdr.chnb().bits(), value: lcdr.ldata().bits() })
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.adc.adc.ier.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.adc.adc.idr.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Stops converting and goes back to single conversions
+    pub fn release(self) -> Adc {
+        let adc = self.adc;
+        adc.adc.chdr.write_with_zero(|w| unsafe { w.bits(0xffff) });
+        adc.adc.mr.modify(|_, w| w.trgen().dis().freerun().off().useq().num_order());
+        adc.adc.emr.modify(|_, w| w.tag().clear_bit());
+        adc
+    }
+}
+
+/// Streams tagged results, see `Sample::from_tagged`
+impl pdc::Receive for Sequenced {
+    type Word = u16;
+    type Channel = ADC;
+
+    fn rx_channel(&self) -> &ADC {
+        &self.adc.adc
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl<PIN> hal::adc::OneShot<ADC, u16, PIN> for Adc
+where
+    PIN: hal::adc::Channel<ADC, ID = u8>,
+{
+    type Error = void::Void;
+
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<u16, void::Void> {
+        Ok(self.convert(PIN::channel()))
+    }
+}
@@ -0,0 +1,111 @@
+/*
+ *    This file (src/adc/compare.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! ADC comparison window
+//!
+//! Every result of the compared channel, or of all channels, is checked
+//! against a window in hardware, raising COMPE on a match.  Set up on the
+//! `Adc`, it keeps running once the ADC is `Sequenced`, so a free running
+//! sequence can watch a sensor and interrupt only when it leaves its range:
+//!
+//! ```ignore
+//! adc.compare(&sensor, Window::Outside(0x400, 0xc00));
+//! adc.listen_compare();
+//! let sampler = adc.sequence(&Sequence::new().then(&sensor), Trigger::FreeRun);
+//! ```
+//!
+//! The PWM fault unit can use the comparison as a fault input as well.
+
+use crate::adc::{take_status, Adc, Channel, Sequenced, ISR_COMPE};
+use crate::pac::adc::emr::CMPMODE_A;
+
+/// Which results match.  CMPMODE and CWR
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Window {
+    /// Below the threshold
+    Below(u16),
+    /// Above the threshold
+    Above(u16),
+    /// Between the low and high thresholds
+    Inside(u16, u16),
+    /// Below the low threshold or above the high one
+    Outside(u16, u16),
+}
+
+impl Window {
+    /// CMPMODE, LOWTHRES and HIGHTHRES
+    fn fields(self) -> (CMPMODE_A, u16, u16) {
+        match self {
+            Window::Below(low) => (CMPMODE_A::LOW, low, 0),
+            Window::Above(high) => (CMPMODE_A::HIGH, 0, high),
+            Window::Inside(low, high) => (CMPMODE_A::IN, low, high),
+            Window::Outside(low, high) => (CMPMODE_A::OUT, low, high),
+        }
+    }
+}
+
+impl Adc {
+    /// Compares the results of `input` against `window`
+    pub fn compare<C: Channel>(&mut self, _input: &C, window: Window) {
+        self.set_window(window, Some(C::ID));
+    }
+
+    /// Compares the results of every channel against `window`
+    pub fn compare_all(&mut self, window: Window) {
+        self.set_window(window, None);
+    }
+
+    fn set_window(&mut self, window: Window, channel: Option<u8>) {
+        let (mode, low, high) = window.fields();
+        assert!(low <= 0xfff && high <= 0xfff);
+
+        self.adc.cwr.write(|w| unsafe { w.lowthres().bits(low).highthres().bits(high) });
+        self.adc.emr.modify(|_, w| unsafe {
+            w.cmpmode().variant(mode)
+                .cmpsel().bits(channel.unwrap_or(0))
+                .cmpall().bit(channel.is_none())
+        });
+    }
+
+    /// CMPFILTER: number of consecutive matches, 1 to 4, before COMPE is
+    /// raised.  Keeps noise from tripping the comparison.
+    pub fn set_compare_filter(&mut self, matches: u8) {
+        assert!((1..=4).contains(&matches));
+        self.adc.emr.modify(|_, w| unsafe { w.cmpfilter().bits(matches - 1) });
+    }
+
+    /// Starts interrupting on comparison matches
+    pub fn listen_compare(&mut self) {
+        self.adc.ier.write_with_zero(|w| w.compe().set_bit());
+    }
+
+    pub fn unlisten_compare(&mut self) {
+        self.adc.idr.write_with_zero(|w| w.compe().set_bit());
+    }
+
+    /// Whether a result matched since the last call
+    pub fn take_compare_match(&mut self) -> bool {
+        take_status(ISR_COMPE) != 0
+    }
+}
+
+impl Sequenced {
+    /// Whether a result matched since the last call
+    pub fn take_compare_match(&mut self) -> bool {
+        take_status(ISR_COMPE) != 0
+    }
+}
@@ -0,0 +1,347 @@
+/*
+ *    This file (src/dacc.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Digital-to-Analog Converter Controller
+//!
+//! One 12-bit converter shared by the DAC0 and DAC1 outputs.  Each sample
+//! written goes to the output picked by USER_SEL, or, in tagged mode, by bits
+//! 13:12 of the sample itself so a single stream can feed both outputs.
+//!
+//! Samples are converted as soon as they're written, or on each trigger edge
+//! when a trigger is set, which together with the PDC plays a buffer out at a
+//! fixed sample rate:
+//!
+//! ```ignore
+//! let config = dacc::Config::new().trigger(Trigger::Tioa0);
+//! let dacc = p.DACC.dacc::<u16>((piob.pb15.into_analog(), ()), config, &mut pmc);
+//! let transfer = dacc.write_all(&WAVEFORM);
+//! ```
+//!
+//! With `u32` samples each write carries two conversions, the low half word
+//! first, halving the PDC traffic.
+
+use core::marker::PhantomData;
+
+use crate::gpio::Analog;
+use crate::gpio::piob::{PB15, PB16};
+use crate::pac::dacc::mr::{STARTUP_A, USER_SEL_A};
+use crate::pac::DACC;
+use crate::pdc::{self, Word};
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::pwm::compare::EventLine;
+
+/// Largest sample value
+pub const MAX_VALUE: u16 = 0xfff;
+
+/// Time the converter needs to power up
+const STARTUP_TIME_US: u32 = 40;
+/// How often the outputs are refreshed, they drift when left longer
+const REFRESH_PERIOD_US: u32 = 100;
+
+/// DAC outputs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Output {
+    Dac0,
+    Dac1,
+}
+
+impl Output {
+    fn user_sel(self) -> USER_SEL_A {
+        match self {
+            Output::Dac0 => USER_SEL_A::CHANNEL0,
+            Output::Dac1 => USER_SEL_A::CHANNEL1,
+        }
+    }
+}
+
+/// How samples find their output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Selection {
+    /// USER_SEL: every sample goes to one output
+    Fixed(Output),
+    /// TAG: bits 13:12 of each sample pick the output, see `tag`
+    Tagged,
+}
+
+/// Conversion start.  TRGSEL
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trigger {
+    /// Rising edge on DATRG
+    External,
+    /// Rising edge on TIOA of timer counter 0 channel 0
+    Tioa0,
+    /// Rising edge on TIOA of timer counter 0 channel 1
+    Tioa1,
+    /// Rising edge on TIOA of timer counter 0 channel 2
+    Tioa2,
+    /// A pulse on a PWM event line, see `pwm::compare`
+    Pwm(EventLine),
+}
+
+impl Trigger {
+    fn trgsel(self) -> u8 {
+        match self {
+            Trigger::External => 0,
+            Trigger::Tioa0 => 1,
+            Trigger::Tioa1 => 2,
+            Trigger::Tioa2 => 3,
+            Trigger::Pwm(EventLine::Line0) => 4,
+            Trigger::Pwm(EventLine::Line1) => 5,
+        }
+    }
+}
+
+/// Tags `value` for `output`, for tagged selection
+pub fn tag(output: Output, value: u16) -> u16 {
+    assert!(value <= MAX_VALUE);
+    match output {
+        Output::Dac0 => value,
+        Output::Dac1 => value | 1 << 12,
+    }
+}
+
+/// DACC configuration
+pub struct Config {
+    selection: Selection,
+    trigger: Option<Trigger>,
+    max_speed: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            selection: Selection::Fixed(Output::Dac0),
+            trigger: None,
+            max_speed: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Converts on trigger edges rather than as soon as a sample is written
+    pub fn trigger(mut self, trigger: Trigger) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// MAXS: skips the wait between conversions, for back to back
+    /// conversions at the full rate
+    pub fn max_speed(mut self, max_speed: bool) -> Self {
+        self.max_speed = max_speed;
+        self
+    }
+}
+
+/// DAC0 pin
+pub trait Dac0Pin<DACC> {
+    const USED: bool;
+}
+
+/// DAC1 pin
+pub trait Dac1Pin<DACC> {
+    const USED: bool;
+}
+
+impl Dac0Pin<DACC> for () {
+    const USED: bool = false;
+}
+
+impl Dac1Pin<DACC> for () {
+    const USED: bool = false;
+}
+
+impl Dac0Pin<DACC> for PB15<Analog> {
+    const USED: bool = true;
+}
+
+impl Dac1Pin<DACC> for PB16<Analog> {
+    const USED: bool = true;
+}
+
+/// Output pins, `(DAC0, DAC1)` with `()` for an unused output
+pub trait Pins<DACC> {
+    /// CHER bits
+    const CHANNELS: u32;
+}
+
+impl<DAC0, DAC1> Pins<DACC> for (DAC0, DAC1)
+where
+    DAC0: Dac0Pin<DACC>,
+    DAC1: Dac1Pin<DACC>,
+{
+    const CHANNELS: u32 = DAC0::USED as u32 | (DAC1::USED as u32) << 1;
+}
+
+/// Transfer widths: `u16` for a sample per write, `u32` for two.  WORD
+pub trait Width: Word {
+    const WORD: bool;
+
+    fn into_bits(self) -> u32;
+}
+
+impl Width for u16 {
+    const WORD: bool = false;
+
+    fn into_bits(self) -> u32 { self as u32 }
+}
+
+impl Width for u32 {
+    const WORD: bool = true;
+
+    fn into_bits(self) -> u32 { self }
+}
+
+/// The DACC counts its startup and refresh times in DACC clock periods
+fn dacc_clock(clocks: &Clocks) -> u32 {
+    clocks.master_clk().0 / 2
+}
+
+/// STARTUP, in DACC clock periods
+fn startup(clocks: &Clocks) -> STARTUP_A {
+    use STARTUP_A::*;
+
+    let periods = (dacc_clock(clocks) / 1_000_000) * STARTUP_TIME_US;
+    [
+        (0, _0), (8, _8), (16, _16), (24, _24), (64, _64), (80, _80), (96, _96), (112, _112),
+        (512, _512), (576, _576), (640, _640), (704, _704), (768, _768), (832, _832), (896, _896), (960, _960),
+        (1024, _1024), (1088, _1088), (1152, _1152), (1216, _1216), (1280, _1280), (1344, _1344), (1408, _1408),
+        (1472, _1472), (1536, _1536), (1600, _1600), (1664, _1664), (1728, _1728), (1792, _1792), (1856, _1856),
+        (1920, _1920), (1984, _1984),
+    ]
+        .iter()
+        .find(|&&(startup, _)| startup >= periods)
+        .map_or(_1984, |&(_, startup)| startup)
+}
+
+/// REFRESH: refresh period = 1024 * REFRESH DACC clock periods
+fn refresh(clocks: &Clocks) -> u8 {
+    let periods = (dacc_clock(clocks) / 1_000_000) * REFRESH_PERIOD_US;
+    (periods / 1024).clamp(1, 0xff) as u8
+}
+
+/// DACC interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// TXRDY: a sample can be written
+    TxReady,
+    /// EOC: a conversion finished
+    EndOfConversion,
+    /// ENDTX: the PDC's current buffer is done
+    EndOfTransfer,
+    /// TXBUFE: both PDC buffers are done
+    BufferEmpty,
+}
+
+/// Digital-to-Analog Converter Controller
+pub struct Dacc<PINS, W> {
+    dacc: DACC,
+    pins: PINS,
+    _width: PhantomData<W>,
+}
+
+pub trait DaccExt {
+    /// Enables the outputs with a pin in `pins`
+    fn dacc<W: Width, PINS: Pins<DACC>>(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Dacc<PINS, W>;
+}
+
+impl DaccExt for DACC {
+    fn dacc<W: Width, PINS: Pins<DACC>>(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Dacc<PINS, W> {
+        assert!(PINS::CHANNELS != 0, "no DAC output");
+        pmc.enable_clock(PeripheralClock::Dacc);
+
+        let clocks = pmc.clocks;
+        self.cr.write_with_zero(|w| w.swrst().set_bit());
+        self.mr.write(|w| unsafe {
+            w.trgen().bit(config.trigger.is_some())
+                .trgsel().bits(config.trigger.map_or(0, Trigger::trgsel))
+                .word().bit(W::WORD)
+                .refresh().bits(refresh(&clocks))
+                .tag().bit(config.selection == Selection::Tagged)
+                .maxs().bit(config.max_speed)
+                .startup().variant(startup(&clocks))
+        });
+        if let Selection::Fixed(output) = config.selection {
+            self.mr.modify(|_, w| w.user_sel().variant(output.user_sel()));
+        }
+
+        // Bias currents for conversion rates up to 1 MHz
+        self.acr.write(|w| unsafe { w.ibctlch0().bits(0b10).ibctlch1().bits(0b10).ibctldaccore().bits(0b01) });
+        self.cher.write_with_zero(|w| unsafe { w.bits(PINS::CHANNELS) });
+
+        Dacc { dacc: self, pins, _width: PhantomData }
+    }
+}
+
+impl<PINS, W: Width> Dacc<PINS, W> {
+    /// Queues a sample, or two with `u32` samples
+    pub fn write(&mut self, sample: W) -> nb::Result<(), void::Void> {
+        if self.dacc.isr.read().txrdy().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.dacc.cdr.write(|w| unsafe { w.data().bits(sample.into_bits()) });
+        Ok(())
+    }
+
+    /// Sends the following samples to `output`, with fixed selection
+    pub fn select(&mut self, output: Output) {
+        self.dacc.mr.modify(|_, w| w.user_sel().variant(output.user_sel()));
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::TxReady => self.dacc.ier.write_with_zero(|w| w.txrdy().set_bit()),
+            Event::EndOfConversion => self.dacc.ier.write_with_zero(|w| w.eoc().set_bit()),
+            Event::EndOfTransfer => self.dacc.ier.write_with_zero(|w| w.endtx().set_bit()),
+            Event::BufferEmpty => self.dacc.ier.write_with_zero(|w| w.txbufe().set_bit()),
+        }
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::TxReady => self.dacc.idr.write_with_zero(|w| w.txrdy().set_bit()),
+            Event::EndOfConversion => self.dacc.idr.write_with_zero(|w| w.eoc().set_bit()),
+            Event::EndOfTransfer => self.dacc.idr.write_with_zero(|w| w.endtx().set_bit()),
+            Event::BufferEmpty => self.dacc.idr.write_with_zero(|w| w.txbufe().set_bit()),
+        }
+    }
+
+    pub fn free(self) -> (DACC, PINS) {
+        self.dacc.chdr.write_with_zero(|w| unsafe { w.bits(0b11) });
+        (self.dacc, self.pins)
+    }
+}
+
+impl<PINS, W: Width> pdc::Transmit for Dacc<PINS, W> {
+    type Word = W;
+    type Channel = DACC;
+
+    fn tx_channel(&self) -> &DACC {
+        &self.dacc
+    }
+}
@@ -27,16 +27,31 @@ const MICRO: u32 = 1_000_000;
 
 macro_rules! timer_hal {
     ($timer_name:ident, $timer_source:ident, $reg_cmr:ident, $reg_rc:ident, $reg_ccr:ident, $reg_cv:ident) => {
-        pub struct $timer_name(pub $timer_source);
+        /// A channel of a TC block, handed out by `TimerCounterExt::split`
+        pub struct $timer_name {
+            _private: (),
+        }
+
+        impl $timer_name {
+            /// Only `split` creates channels, one of each per block
+            pub(crate) unsafe fn steal() -> Self {
+                $timer_name { _private: () }
+            }
+
+            /// The block's registers, the channel only touches its own
+            pub(crate) fn regs(&self) -> &<$timer_source as core::ops::Deref>::Target {
+                unsafe { &*$timer_source::ptr() }
+            }
+        }
 
         impl Delay<$timer_name> {
             pub fn new(source: $timer_name, clocks: Clocks) -> Self {
                 Delay { source, clocks }
             }
 
-            /// Releases the TimerCouunter (TC0...TC2) resource
-            pub fn free(self) -> $timer_source {
-                self.source.0
+            /// Releases the TimerCounter channel
+            pub fn free(self) -> $timer_name {
+                self.source
             }
         }
 
@@ -62,7 +77,7 @@ macro_rules! timer_hal {
             fn delay_us(&mut self, us: u32) {
                 let cycles: u32 = us * ((CLOCK_SPEED / DIVIDER) / MICRO);
 
-                let timer = &self.source.0;
+                let timer = self.source.regs();
 
                 // Disable write protection
                 timer
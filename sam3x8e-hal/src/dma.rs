@@ -0,0 +1,567 @@
+/*
+ *    This file (src/dma.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! DMA Controller.  Datasheet §24
+//!
+//! The DMAC is split into its six channels, each of which can be handed to a
+//! different driver.  Channels 0 to 2 have an 8 byte FIFO, channels 3 to 5 a 32
+//! byte one, so the latter are the better fit for memory to memory copies and
+//! fast peripherals.
+//!
+//! Peripheral transfers are paced by the peripheral's hardware handshake
+//! interface, see `Handshake`.  Buffers use the same ownership rules as the
+//! PDC: the transfer owns them until it's over.
+
+use core::marker::PhantomData;
+use core::mem;
+use core::sync::atomic::{self, Ordering};
+
+use crate::latch::Latch;
+use crate::pac::DMAC;
+use crate::pdc::{ReadBuffer, Word, WriteBuffer};
+use crate::pmc::{PeripheralClock, Pmc};
+
+/// Largest number of transfers in a single buffer
+pub const MAX_TRANSFERS: usize = 0xffff;
+
+/// Hardware handshake interfaces.  Datasheet §24.3
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Handshake {
+    /// Both directions share the one interface
+    Hsmci,
+    Spi0Tx,
+    Spi0Rx,
+    SscTx,
+    SscRx,
+    Twi0Tx,
+    Twi0Rx,
+    Twi1Tx,
+    Twi1Rx,
+    Usart0Tx,
+    Usart0Rx,
+    Usart1Tx,
+    Usart1Rx,
+}
+
+impl Handshake {
+    fn number(self) -> u32 {
+        match self {
+            Handshake::Hsmci => 0,
+            Handshake::Spi0Tx => 1,
+            Handshake::Spi0Rx => 2,
+            Handshake::SscTx => 3,
+            Handshake::SscRx => 4,
+            Handshake::Twi0Tx => 7,
+            Handshake::Twi0Rx => 8,
+            Handshake::Twi1Tx => 9,
+            Handshake::Twi1Rx => 10,
+            Handshake::Usart0Tx => 11,
+            Handshake::Usart0Rx => 12,
+            Handshake::Usart1Tx => 13,
+            Handshake::Usart1Rx => 14,
+        }
+    }
+}
+
+/// A peripheral register the DMAC can read from, one transfer per request
+///
+/// # Safety
+///
+/// `source_address` must be the address of a register of the peripheral
+/// behind `HANDSHAKE` that can be read `Word` wide without side effects
+/// other than the peripheral's own.
+pub unsafe trait PeripheralSource {
+    type Word: Word;
+    const HANDSHAKE: Handshake;
+
+    fn source_address(&self) -> u32;
+}
+
+/// A peripheral register the DMAC can write to, one transfer per request
+///
+/// # Safety
+///
+/// `destination_address` must be the address of a register of the peripheral
+/// behind `HANDSHAKE` that can be written `Word` wide.
+pub unsafe trait PeripheralDestination {
+    type Word: Word;
+    const HANDSHAKE: Handshake;
+
+    fn destination_address(&self) -> u32;
+}
+
+/// Channel interrupt events
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    /// BTC: a buffer has been transferred
+    BufferComplete,
+    /// CBTC: the last buffer of a chain has been transferred
+    ChainComplete,
+    /// ERR: the channel hit an AHB error
+    Error,
+}
+
+/// Events that fired since they were last taken
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Events {
+    pub buffer_complete: bool,
+    pub chain_complete: bool,
+    pub error: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Error {
+    /// The channel hit an AHB error and was disabled
+    Bus,
+}
+
+// EBCISR is cleared on read, so every read is latched here and each channel
+// only takes its own flags out.
+static STATUS: Latch = Latch::new(!0);
+
+fn take_status(mask: u32) -> u32 {
+    STATUS.take(unsafe { (*DMAC::ptr()).ebcisr.read().bits() }, mask)
+}
+
+pub trait DmacExt {
+    /// Enables the DMAC and splits it into its channels
+    fn split(self, pmc: &mut Pmc) -> Channels;
+}
+
+/// The six DMAC channels
+pub struct Channels {
+    pub ch0: Channel<C0>,
+    pub ch1: Channel<C1>,
+    pub ch2: Channel<C2>,
+    pub ch3: Channel<C3>,
+    pub ch4: Channel<C4>,
+    pub ch5: Channel<C5>,
+}
+
+impl DmacExt for DMAC {
+    fn split(self, pmc: &mut Pmc) -> Channels {
+        pmc.enable_clock(PeripheralClock::Dmac);
+
+        // Disable write protection
+        self.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+        self.en.write(|w| w.enable().clear_bit());
+        self.chdr.write_with_zero(|w|
+            w
+            .dis0().set_bit()
+            .dis1().set_bit()
+            .dis2().set_bit()
+            .dis3().set_bit()
+            .dis4().set_bit()
+            .dis5().set_bit()
+        );
+
+        // Clear anything left over
+        self.ebcisr.read();
+
+        self.gcfg.write(|w| w.arb_cfg().round_robin());
+        self.en.write(|w| w.enable().set_bit());
+
+        Channels {
+            ch0: Channel { _id: PhantomData },
+            ch1: Channel { _id: PhantomData },
+            ch2: Channel { _id: PhantomData },
+            ch3: Channel { _id: PhantomData },
+            ch4: Channel { _id: PhantomData },
+            ch5: Channel { _id: PhantomData },
+        }
+    }
+}
+
+/// A DMAC channel
+pub struct Channel<ID> {
+    _id: PhantomData<ID>,
+}
+
+pub struct C0;
+pub struct C1;
+pub struct C2;
+pub struct C3;
+pub struct C4;
+pub struct C5;
+
+/// A linked list item, one per buffer in a chain.  Must live in RAM for as
+/// long as the chain runs.  Datasheet §24.4.5
+#[repr(C, align(4))]
+#[derive(Clone, Copy)]
+pub struct Descriptor {
+    saddr: u32,
+    daddr: u32,
+    ctrla: u32,
+    ctrlb: u32,
+    dscr: u32,
+}
+
+impl Descriptor {
+    pub const fn new() -> Self {
+        Descriptor { saddr: 0, daddr: 0, ctrla: 0, ctrlb: 0, dscr: 0 }
+    }
+}
+
+impl Default for Descriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Register fields used to build CTRLA / CTRLB / CFG values, shared between the
+// channel registers and descriptors.  Datasheet §24.7
+const CTRLA_SRC_WIDTH: u32 = 24;
+const CTRLA_DST_WIDTH: u32 = 28;
+
+const CTRLB_SRC_DSCR: u32 = 1 << 16;
+const CTRLB_DST_DSCR: u32 = 1 << 20;
+const CTRLB_FC: u32 = 21;
+const CTRLB_SRC_INCR: u32 = 24;
+const CTRLB_DST_INCR: u32 = 28;
+
+const FC_MEM2MEM: u32 = 0;
+const FC_MEM2PER: u32 = 1;
+const FC_PER2MEM: u32 = 2;
+
+const INCR_INCREMENTING: u32 = 0;
+const INCR_FIXED: u32 = 2;
+
+const CFG_SRC_PER: u32 = 0;
+const CFG_DST_PER: u32 = 4;
+const CFG_SRC_H2SEL: u32 = 1 << 9;
+const CFG_DST_H2SEL: u32 = 1 << 13;
+const CFG_AHB_PROT: u32 = 1 << 24;
+const CFG_FIFOCFG_ASAP: u32 = 2 << 28;
+
+fn width<W: Word>() -> u32 {
+    match mem::size_of::<W>() {
+        1 => 0,
+        2 => 1,
+        _ => 2,
+    }
+}
+
+fn ctrla<S: Word, D: Word>(count: usize) -> u32 {
+    assert!(count > 0 && count <= MAX_TRANSFERS);
+    count as u32 | width::<S>() << CTRLA_SRC_WIDTH | width::<D>() << CTRLA_DST_WIDTH
+}
+
+/// Channel register values for a transfer
+struct Registers {
+    saddr: u32,
+    daddr: u32,
+    dscr: u32,
+    ctrla: u32,
+    ctrlb: u32,
+    cfg: u32,
+}
+
+fn ctrlb(flow: u32, source_increment: u32, destination_increment: u32) -> u32 {
+    flow << CTRLB_FC | source_increment << CTRLB_SRC_INCR | destination_increment << CTRLB_DST_INCR
+}
+
+/// Raw per channel register access
+pub trait ChannelRegisters {
+    /// Bit of this channel in the shared registers
+    const INDEX: u32;
+
+    /// Programs the channel registers
+    ///
+    /// # Safety
+    ///
+    /// The channel must be disabled.  The memory and descriptors the values
+    /// point at must stay valid, and not be otherwise accessed, until the
+    /// channel is done with them.
+    unsafe fn program(&mut self, saddr: u32, daddr: u32, dscr: u32, ctrla: u32, ctrlb: u32, cfg: u32);
+
+    fn enable(&mut self);
+
+    fn disable(&mut self);
+
+    fn is_enabled(&self) -> bool;
+}
+
+macro_rules! channel {
+    ($CX:ident, $index:expr, $saddr:ident, $daddr:ident, $dscr:ident, $ctrla:ident, $ctrlb:ident, $cfg:ident, $ena:ident, $dis:ident) => {
+        impl ChannelRegisters for Channel<$CX> {
+            const INDEX: u32 = $index;
+
+            unsafe fn program(&mut self, saddr: u32, daddr: u32, dscr: u32, ctrla: u32, ctrlb: u32, cfg: u32) {
+                let dmac = &*DMAC::ptr();
+                dmac.$saddr.write(|w| w.saddr().bits(saddr));
+                dmac.$daddr.write(|w| w.daddr().bits(daddr));
+                dmac.$dscr.write(|w| w.dscr().bits(dscr));
+                dmac.$ctrla.write(|w| w.bits(ctrla));
+                dmac.$ctrlb.write(|w| w.bits(ctrlb));
+                dmac.$cfg.write(|w| w.bits(cfg));
+            }
+
+            fn enable(&mut self) {
+                unsafe { (*DMAC::ptr()).cher.write_with_zero(|w| w.$ena().set_bit()) };
+            }
+
+            fn disable(&mut self) {
+                unsafe { (*DMAC::ptr()).chdr.write_with_zero(|w| w.$dis().set_bit()) };
+            }
+
+            fn is_enabled(&self) -> bool {
+                unsafe { (*DMAC::ptr()).chsr.read().$ena().bit_is_set() }
+            }
+        }
+    };
+}
+
+channel!(C0, 0, saddr0, daddr0, dscr0, ctrla0, ctrlb0, cfg0, ena0, dis0);
+channel!(C1, 1, saddr1, daddr1, dscr1, ctrla1, ctrlb1, cfg1, ena1, dis1);
+channel!(C2, 2, saddr2, daddr2, dscr2, ctrla2, ctrlb2, cfg2, ena2, dis2);
+channel!(C3, 3, saddr3, daddr3, dscr3, ctrla3, ctrlb3, cfg3, ena3, dis3);
+channel!(C4, 4, saddr4, daddr4, dscr4, ctrla4, ctrlb4, cfg4, ena4, dis4);
+channel!(C5, 5, saddr5, daddr5, dscr5, ctrla5, ctrlb5, cfg5, ena5, dis5);
+
+impl<ID> Channel<ID>
+where
+    Channel<ID>: ChannelRegisters,
+{
+    fn event_mask(event: Event) -> u32 {
+        match event {
+            Event::BufferComplete => 1 << Self::INDEX,
+            Event::ChainComplete => 1 << (Self::INDEX + 8),
+            Event::Error => 1 << (Self::INDEX + 16),
+        }
+    }
+
+    /// Starts listening for an interrupt event on this channel
+    pub fn listen(&mut self, event: Event) {
+        let mask = Self::event_mask(event);
+        unsafe { (*DMAC::ptr()).ebcier.write_with_zero(|w| w.bits(mask)) };
+    }
+
+    /// Stops listening for an interrupt event on this channel
+    pub fn unlisten(&mut self, event: Event) {
+        let mask = Self::event_mask(event);
+        unsafe { (*DMAC::ptr()).ebcidr.write_with_zero(|w| w.bits(mask)) };
+    }
+
+    /// Returns and clears the events that fired on this channel.  Call this
+    /// from the DMAC interrupt handler for every channel in use.
+    pub fn take_events(&mut self) -> Events {
+        let status = take_status(
+            Self::event_mask(Event::BufferComplete) |
+            Self::event_mask(Event::ChainComplete) |
+            Self::event_mask(Event::Error)
+        );
+
+        Events {
+            buffer_complete: status & Self::event_mask(Event::BufferComplete) != 0,
+            chain_complete: status & Self::event_mask(Event::ChainComplete) != 0,
+            error: status & Self::event_mask(Event::Error) != 0,
+        }
+    }
+
+    fn start<PAYLOAD>(mut self, registers: Registers, payload: PAYLOAD) -> Transfer<Self, PAYLOAD> {
+        let Registers { saddr, daddr, dscr, ctrla, ctrlb, cfg } = registers;
+
+        self.disable();
+        take_status(Self::event_mask(Event::Error));
+
+        // The buffers have to be handed over before the DMAC touches them
+        atomic::compiler_fence(Ordering::Release);
+        unsafe { self.program(saddr, daddr, dscr, ctrla, ctrlb, cfg) };
+        self.enable();
+
+        Transfer { channel: self, payload }
+    }
+
+    /// Copies `source` into `destination`, both must hold the same number of
+    /// transfers
+    pub fn memory_to_memory<S, D>(self, source: S, mut destination: D) -> Transfer<Self, (S, D)>
+    where
+        S: ReadBuffer,
+        D: WriteBuffer,
+    {
+        let (saddr, source_count) = source.read_buffer();
+        let (daddr, destination_count) = destination.write_buffer();
+        assert!(source_count * mem::size_of::<S::Word>() == destination_count * mem::size_of::<D::Word>());
+
+        // BTSIZE counts source transfers
+        let ctrla = ctrla::<S::Word, D::Word>(source_count);
+        let ctrlb = ctrlb(FC_MEM2MEM, INCR_INCREMENTING, INCR_INCREMENTING) | CTRLB_SRC_DSCR | CTRLB_DST_DSCR;
+        let cfg = CFG_AHB_PROT | CFG_FIFOCFG_ASAP;
+
+        self.start(Registers { saddr: saddr as u32, daddr: daddr as u32, dscr: 0, ctrla, ctrlb, cfg }, (source, destination))
+    }
+
+    /// Sends `buffer` to a peripheral
+    pub fn memory_to_peripheral<B, P>(self, buffer: B, peripheral: P) -> Transfer<Self, (B, P)>
+    where
+        B: ReadBuffer<Word = P::Word>,
+        P: PeripheralDestination,
+    {
+        let (saddr, count) = buffer.read_buffer();
+        let daddr = peripheral.destination_address();
+
+        let ctrla = ctrla::<P::Word, P::Word>(count);
+        let ctrlb = ctrlb(FC_MEM2PER, INCR_INCREMENTING, INCR_FIXED) | CTRLB_SRC_DSCR | CTRLB_DST_DSCR;
+        let cfg = CFG_AHB_PROT | CFG_FIFOCFG_ASAP | CFG_DST_H2SEL | P::HANDSHAKE.number() << CFG_DST_PER;
+
+        self.start(Registers { saddr: saddr as u32, daddr, dscr: 0, ctrla, ctrlb, cfg }, (buffer, peripheral))
+    }
+
+    /// Fills `buffer` from a peripheral
+    pub fn peripheral_to_memory<P, B>(self, peripheral: P, mut buffer: B) -> Transfer<Self, (P, B)>
+    where
+        P: PeripheralSource,
+        B: WriteBuffer<Word = P::Word>,
+    {
+        let saddr = peripheral.source_address();
+        let (daddr, count) = buffer.write_buffer();
+
+        let ctrla = ctrla::<P::Word, P::Word>(count);
+        let ctrlb = ctrlb(FC_PER2MEM, INCR_FIXED, INCR_INCREMENTING) | CTRLB_SRC_DSCR | CTRLB_DST_DSCR;
+        let cfg = CFG_AHB_PROT | CFG_FIFOCFG_ASAP | CFG_SRC_H2SEL | P::HANDSHAKE.number() << CFG_SRC_PER;
+
+        self.start(Registers { saddr, daddr: daddr as u32, dscr: 0, ctrla, ctrlb, cfg }, (peripheral, buffer))
+    }
+
+    /// Sends a chain of buffers to a peripheral, one descriptor per buffer.
+    /// BufferComplete fires after each buffer, ChainComplete after the last.
+    pub fn memory_to_peripheral_chain<B, P, const N: usize>(
+        self,
+        buffers: [B; N],
+        peripheral: P,
+        descriptors: &'static mut [Descriptor; N],
+    ) -> Transfer<Self, ([B; N], P, &'static mut [Descriptor; N])>
+    where
+        B: ReadBuffer<Word = P::Word>,
+        P: PeripheralDestination,
+    {
+        assert!(N > 0);
+        let daddr = peripheral.destination_address();
+        let ctrlb = ctrlb(FC_MEM2PER, INCR_INCREMENTING, INCR_FIXED);
+
+        for (index, buffer) in buffers.iter().enumerate() {
+            let (saddr, count) = buffer.read_buffer();
+            descriptors[index] = Descriptor {
+                saddr: saddr as u32,
+                daddr,
+                ctrla: ctrla::<P::Word, P::Word>(count),
+                ctrlb,
+                dscr: 0,
+            };
+        }
+
+        link(&mut descriptors[..]);
+
+        let cfg = CFG_AHB_PROT | CFG_FIFOCFG_ASAP | CFG_DST_H2SEL | P::HANDSHAKE.number() << CFG_DST_PER;
+        let dscr = &descriptors[0] as *const Descriptor as u32;
+
+        self.start(Registers { saddr: 0, daddr: 0, dscr, ctrla: 0, ctrlb, cfg }, (buffers, peripheral, descriptors))
+    }
+
+    /// Fills a chain of buffers from a peripheral, one descriptor per buffer.
+    /// BufferComplete fires after each buffer, ChainComplete after the last.
+    pub fn peripheral_to_memory_chain<P, B, const N: usize>(
+        self,
+        peripheral: P,
+        mut buffers: [B; N],
+        descriptors: &'static mut [Descriptor; N],
+    ) -> Transfer<Self, (P, [B; N], &'static mut [Descriptor; N])>
+    where
+        P: PeripheralSource,
+        B: WriteBuffer<Word = P::Word>,
+    {
+        assert!(N > 0);
+        let saddr = peripheral.source_address();
+        let ctrlb = ctrlb(FC_PER2MEM, INCR_FIXED, INCR_INCREMENTING);
+
+        for (index, buffer) in buffers.iter_mut().enumerate() {
+            let (daddr, count) = buffer.write_buffer();
+            descriptors[index] = Descriptor {
+                saddr,
+                daddr: daddr as u32,
+                ctrla: ctrla::<P::Word, P::Word>(count),
+                ctrlb,
+                dscr: 0,
+            };
+        }
+
+        link(&mut descriptors[..]);
+
+        let cfg = CFG_AHB_PROT | CFG_FIFOCFG_ASAP | CFG_SRC_H2SEL | P::HANDSHAKE.number() << CFG_SRC_PER;
+        let dscr = &descriptors[0] as *const Descriptor as u32;
+
+        self.start(Registers { saddr: 0, daddr: 0, dscr, ctrla: 0, ctrlb, cfg }, (peripheral, buffers, descriptors))
+    }
+}
+
+/// Points every descriptor at the one after it and marks the last one as the
+/// end of the chain
+fn link(descriptors: &mut [Descriptor]) {
+    let last = descriptors.len() - 1;
+
+    for index in 0..last {
+        descriptors[index].dscr = &descriptors[index + 1] as *const Descriptor as u32;
+    }
+
+    descriptors[last].ctrlb |= CTRLB_SRC_DSCR | CTRLB_DST_DSCR;
+}
+
+/// A transfer in progress on a channel.  The channel and everything the
+/// transfer borrowed are handed back once it's over.
+pub struct Transfer<CHANNEL, PAYLOAD> {
+    channel: CHANNEL,
+    payload: PAYLOAD,
+}
+
+impl<ID, PAYLOAD> Transfer<Channel<ID>, PAYLOAD>
+where
+    Channel<ID>: ChannelRegisters,
+{
+    /// The channel disables itself once the last buffer has been transferred
+    pub fn is_done(&self) -> bool {
+        !self.channel.is_enabled()
+    }
+
+    /// Checks on the transfer, reporting bus errors
+    pub fn poll(&mut self) -> nb::Result<(), Error> {
+        if take_status(Channel::<ID>::event_mask(Event::Error)) != 0 {
+            self.channel.disable();
+            return Err(nb::Error::Other(Error::Bus));
+        }
+
+        match self.is_done() {
+            true => Ok(()),
+            false => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Blocks until the transfer is over, see `poll` to catch bus errors
+    pub fn wait(mut self) -> (Channel<ID>, PAYLOAD) {
+        while let Err(nb::Error::WouldBlock) = self.poll() {}
+        self.stop()
+    }
+
+    /// Stops the transfer wherever it's got to
+    pub fn stop(mut self) -> (Channel<ID>, PAYLOAD) {
+        self.channel.disable();
+        while self.channel.is_enabled() {}
+
+        atomic::compiler_fence(Ordering::Acquire);
+        (self.channel, self.payload)
+    }
+}
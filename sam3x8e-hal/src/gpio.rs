@@ -54,6 +54,9 @@ pub struct PushPull;
 /// Open drain output
 pub struct OpenDrain;
 
+/// Analog mode, for the ADC and DACC
+pub struct Analog;
+
 pub struct PeripheralA;
 pub struct PeripheralB;
 
@@ -0,0 +1,325 @@
+/*
+ *    This file (src/i2c.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! I²C master over the Two-wire Interface.  Datasheet §33
+//!
+//! The TWI can't issue a repeated start on its own.  It only does so between
+//! an internal address of up to three bytes and the data that follows, so
+//! `WriteRead` is limited to writes of up to three bytes.
+
+use cortex_m::asm;
+
+use crate::gpio::PeripheralA;
+use crate::gpio::pioa::{PA17, PA18};
+use crate::gpio::piob::{PB12, PB13};
+use crate::hal::blocking::i2c::{Read, Write, WriteRead};
+use crate::pac::{PIOA, PIOB, TWI0, TWI1};
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::time::Hertz;
+
+pub mod slave;
+
+/// I²C errors
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The slave didn't acknowledge its address or a data byte
+    Nack,
+    /// Another master won the bus
+    ArbitrationLost,
+    /// A byte was received before the previous one was read
+    Overrun,
+    /// A `WriteRead` write was longer than the three byte internal address
+    WriteTooLong,
+    /// SDA is still held low after clocking SCL
+    BusStuck,
+}
+
+/// Internal (register) address sent after the device address.  Datasheet §33.8.3.4
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InternalAddress {
+    None,
+    OneByte(u8),
+    TwoBytes(u16),
+    ThreeBytes(u32),
+}
+
+impl InternalAddress {
+    /// From the big endian bytes of a short write
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match *bytes {
+            [] => Ok(InternalAddress::None),
+            [a] => Ok(InternalAddress::OneByte(a)),
+            [a, b] => Ok(InternalAddress::TwoBytes(u16::from_be_bytes([a, b]))),
+            [a, b, c] => Ok(InternalAddress::ThreeBytes(u32::from_be_bytes([0, a, b, c]))),
+            _ => Err(Error::WriteTooLong),
+        }
+    }
+
+    fn value(self) -> u32 {
+        match self {
+            InternalAddress::None => 0,
+            InternalAddress::OneByte(address) => address as u32,
+            InternalAddress::TwoBytes(address) => address as u32,
+            InternalAddress::ThreeBytes(address) => address & 0x00ff_ffff,
+        }
+    }
+}
+
+pub trait SdaPin<TWI> {}
+pub trait SclPin<TWI> {}
+
+impl SdaPin<TWI0> for PA17<PeripheralA> {}
+impl SclPin<TWI0> for PA18<PeripheralA> {}
+impl SdaPin<TWI1> for PB12<PeripheralA> {}
+impl SclPin<TWI1> for PB13<PeripheralA> {}
+
+/// Works out CKDIV / CHDIV / CLDIV for a bus frequency.  Each half period
+/// lasts (xxDIV * 2^CKDIV + 4) master clock periods.  Above 384 kHz the low
+/// period is held at the fast mode minimum and the high period takes up the
+/// rest.  Datasheet §33.11.5
+fn clock_dividers(clocks: &Clocks, frequency: Hertz) -> (u8, u8, u8) {
+    const LOW_LEVEL_LIMIT: u32 = 384_000;
+
+    let master_clk = clocks.master_clk().0;
+    let frequency = frequency.0;
+    assert!(frequency > 0 && frequency <= 400_000);
+
+    let divider = |half_frequency: u32| {
+        (master_clk / (half_frequency * 2)).checked_sub(4).expect("master clock too slow for the TWI frequency")
+    };
+
+    let (mut low, mut high) = if frequency > LOW_LEVEL_LIMIT {
+        (divider(LOW_LEVEL_LIMIT), divider(2 * frequency - LOW_LEVEL_LIMIT))
+    } else {
+        let half = divider(frequency);
+        (half, half)
+    };
+
+    let mut ckdiv = 0;
+    while (low > 255 || high > 255) && ckdiv < 7 {
+        ckdiv += 1;
+        low /= 2;
+        high /= 2;
+    }
+    assert!(low <= 255 && high <= 255);
+
+    (low as u8, high as u8, ckdiv)
+}
+
+/// I²C master
+pub struct I2c<TWI, PINS> {
+    twi: TWI,
+    pins: PINS,
+    clocks: Clocks,
+}
+
+pub trait I2cExt<PINS>: Sized {
+    /// Configures the TWI as a master running SCL at `frequency`, up to 400 kHz
+    fn i2c<F: Into<Hertz>>(self, pins: PINS, frequency: F, pmc: &mut Pmc) -> I2c<Self, PINS>;
+}
+
+macro_rules! i2c {
+    ($TWIX:ident, $twix:ident, $clock:ident, $PIOX:ident, $sda:ident, $scl:ident) => {
+        impl<SDA, SCL> I2cExt<(SDA, SCL)> for $TWIX
+        where
+            SDA: SdaPin<$TWIX>,
+            SCL: SclPin<$TWIX>,
+        {
+            fn i2c<F: Into<Hertz>>(self, pins: (SDA, SCL), frequency: F, pmc: &mut Pmc) -> I2c<$TWIX, (SDA, SCL)> {
+                pmc.enable_clock(PeripheralClock::$clock);
+
+                self.cr.write_with_zero(|w| w.swrst().set_bit());
+                // Reading RHR after a reset clears any stale RXRDY
+                self.rhr.read();
+
+                let (cldiv, chdiv, ckdiv) = clock_dividers(&pmc.clocks, frequency.into());
+                self.cwgr.write(|w| unsafe { w.cldiv().bits(cldiv).chdiv().bits(chdiv).ckdiv().bits(ckdiv) });
+
+                self.cr.write_with_zero(|w| w.svdis().set_bit().msen().set_bit());
+
+                I2c { twi: self, pins, clocks: pmc.clocks }
+            }
+        }
+
+        impl<PINS> I2c<$TWIX, PINS> {
+            /// Reads SR, turning the error flags it clears into an error
+            fn status(&self) -> Result<crate::pac::$twix::sr::R, Error> {
+                let sr = self.twi.sr.read();
+
+                if sr.nack().bit_is_set() {
+                    Err(Error::Nack)
+                } else if sr.arblst().bit_is_set() {
+                    Err(Error::ArbitrationLost)
+                } else if sr.ovre().bit_is_set() {
+                    Err(Error::Overrun)
+                } else {
+                    Ok(sr)
+                }
+            }
+
+            fn wait_for_completion(&self) -> Result<(), Error> {
+                while self.status()?.txcomp().bit_is_clear() {}
+                Ok(())
+            }
+
+            fn set_mode(&self, address: u8, internal: InternalAddress, read: bool) {
+                self.twi.mmr.write(|w| {
+                    let w = match internal {
+                        InternalAddress::None => w.iadrsz().none(),
+                        InternalAddress::OneByte(_) => w.iadrsz()._1_byte(),
+                        InternalAddress::TwoBytes(_) => w.iadrsz()._2_byte(),
+                        InternalAddress::ThreeBytes(_) => w.iadrsz()._3_byte(),
+                    };
+
+                    unsafe { w.mread().bit(read).dadr().bits(address) }
+                });
+
+                self.twi.iadr.write(|w| unsafe { w.iadr().bits(internal.value()) });
+            }
+
+            /// Writes `bytes` to the slave at `address`, after the internal
+            /// address if there is one.  An empty write sends just the address,
+            /// which is handy for probing.
+            pub fn write_to(&mut self, address: u8, internal: InternalAddress, bytes: &[u8]) -> Result<(), Error> {
+                self.set_mode(address, internal, false);
+
+                if bytes.is_empty() {
+                    self.twi.cr.write_with_zero(|w| w.quick().set_bit());
+                    return self.wait_for_completion();
+                }
+
+                for &byte in bytes {
+                    while self.status()?.txrdy().bit_is_clear() {}
+                    self.twi.thr.write(|w| unsafe { w.txdata().bits(byte) });
+                }
+
+                self.twi.cr.write_with_zero(|w| w.stop().set_bit());
+                while self.status()?.txrdy().bit_is_clear() {}
+                self.wait_for_completion()
+            }
+
+            /// Reads into `buffer` from the slave at `address`, after writing
+            /// the internal address if there is one
+            pub fn read_from(&mut self, address: u8, internal: InternalAddress, buffer: &mut [u8]) -> Result<(), Error> {
+                if buffer.is_empty() {
+                    return Ok(());
+                }
+
+                self.set_mode(address, internal, true);
+
+                // STOP has to be requested while the last byte is being received
+                let last = buffer.len() - 1;
+                match last {
+                    0 => self.twi.cr.write_with_zero(|w| w.start().set_bit().stop().set_bit()),
+                    _ => self.twi.cr.write_with_zero(|w| w.start().set_bit()),
+                }
+
+                for (index, byte) in buffer.iter_mut().enumerate() {
+                    if index == last && last > 0 {
+                        self.twi.cr.write_with_zero(|w| w.stop().set_bit());
+                    }
+
+                    while self.status()?.rxrdy().bit_is_clear() {}
+                    *byte = self.twi.rhr.read().rxdata().bits();
+                }
+
+                self.wait_for_completion()
+            }
+
+            /// Frees a bus left with SDA held low by a slave that was reset
+            /// or interrupted mid-byte: SCL is clocked by hand until the slave
+            /// lets go of SDA, then a STOP is sent.
+            pub fn recover_bus(&mut self) -> Result<(), Error> {
+                // Half an SCL period at 100 kHz
+                let half_period = self.clocks.processor_clk().0 / 200_000;
+                let pio = unsafe { &*$PIOX::ptr() };
+
+                self.twi.cr.write_with_zero(|w| w.msdis().set_bit());
+
+                // Open drain outputs, released
+                pio.mder.write_with_zero(|w| w.$sda().set_bit().$scl().set_bit());
+                pio.sodr.write_with_zero(|w| w.$sda().set_bit().$scl().set_bit());
+                pio.oer.write_with_zero(|w| w.$sda().set_bit().$scl().set_bit());
+                pio.per.write_with_zero(|w| w.$sda().set_bit().$scl().set_bit());
+
+                for _ in 0..9 {
+                    if pio.pdsr.read().$sda().bit_is_set() {
+                        break;
+                    }
+
+                    pio.codr.write_with_zero(|w| w.$scl().set_bit());
+                    asm::delay(half_period);
+                    pio.sodr.write_with_zero(|w| w.$scl().set_bit());
+                    asm::delay(half_period);
+                }
+
+                // STOP: SDA rising while SCL is high
+                pio.codr.write_with_zero(|w| w.$sda().set_bit());
+                asm::delay(half_period);
+                pio.sodr.write_with_zero(|w| w.$sda().set_bit());
+                asm::delay(half_period);
+
+                let released = pio.pdsr.read().$sda().bit_is_set();
+
+                // Hand the lines back to the TWI
+                pio.pdr.write_with_zero(|w| w.$sda().set_bit().$scl().set_bit());
+                self.twi.cr.write_with_zero(|w| w.msen().set_bit());
+
+                match released {
+                    true => Ok(()),
+                    false => Err(Error::BusStuck),
+                }
+            }
+
+            /// Releases the TWI peripheral and pins
+            pub fn free(self) -> ($TWIX, PINS) {
+                self.twi.cr.write_with_zero(|w| w.msdis().set_bit());
+                (self.twi, self.pins)
+            }
+        }
+
+        impl<PINS> Write for I2c<$TWIX, PINS> {
+            type Error = Error;
+
+            fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+                self.write_to(address, InternalAddress::None, bytes)
+            }
+        }
+
+        impl<PINS> Read for I2c<$TWIX, PINS> {
+            type Error = Error;
+
+            fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+                self.read_from(address, InternalAddress::None, buffer)
+            }
+        }
+
+        impl<PINS> WriteRead for I2c<$TWIX, PINS> {
+            type Error = Error;
+
+            /// `bytes` goes out as the internal address, so it's limited to
+            /// three bytes
+            fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+                let internal = InternalAddress::from_bytes(bytes)?;
+                self.read_from(address, internal, buffer)
+            }
+        }
+    };
+}
+
+i2c!(TWI0, twi0, Twi0, PIOA, p17, p18);
+i2c!(TWI1, twi1, Twi1, PIOB, p12, p13);
@@ -0,0 +1,215 @@
+/*
+ *    This file (src/i2c/slave.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! I²C slave over the Two-wire Interface.  Datasheet §33.10
+//!
+//! The TWI answers to its own address and to the general call address.  It
+//! stretches SCL while it waits on the application: until the received byte
+//! has been read when the master writes, until the next byte has been written
+//! when the master reads.  `activity` turns the status flags into a stream of
+//! events, call it in a loop from the TWI interrupt handler:
+//!
+//! ```ignore
+//! while let Ok(activity) = slave.activity() {
+//!     match activity {
+//!         Activity::AddressMatched(Direction::Write) => index = None,
+//!         Activity::Written(byte) => registers.write(&mut index, byte),
+//!         Activity::Reading => slave.transmit(registers.read(&mut index)),
+//!         _ => {},
+//!     }
+//! }
+//! ```
+
+use crate::i2c::{Error, SclPin, SdaPin};
+use crate::pac::{TWI0, TWI1};
+use crate::pmc::{PeripheralClock, Pmc};
+
+/// Transfer direction, from the master's point of view
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+/// What the master is up to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Activity {
+    /// The master addressed this slave
+    AddressMatched(Direction),
+    /// The master sent the general call address and is going to write
+    GeneralCall,
+    /// The master wrote a byte
+    Written(u8),
+    /// The master wants the next byte, see `transmit`.  SCL is held low until
+    /// it's written.
+    Reading,
+    /// The access ended with a STOP or a repeated START to another address
+    Stop,
+}
+
+/// Interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// SVACC: this slave has been addressed
+    AddressMatch,
+    /// GACC: the general call address has been received
+    GeneralCall,
+    /// EOSACC: the access is over
+    EndOfAccess,
+    /// A byte has been received
+    RxReady,
+    /// The transmit holding register is empty
+    TxReady,
+    Overrun,
+}
+
+/// Status flags cleared by reading SR, kept until they've been reported
+#[derive(Default)]
+struct Latched {
+    general_call: bool,
+    end_of_access: bool,
+    overrun: bool,
+}
+
+/// I²C slave
+pub struct I2cSlave<TWI, PINS> {
+    twi: TWI,
+    pins: PINS,
+    latched: Latched,
+    in_access: bool,
+}
+
+pub trait I2cSlaveExt<PINS>: Sized {
+    /// Configures the TWI as a slave answering to the 7 bit `address`
+    fn i2c_slave(self, pins: PINS, address: u8, pmc: &mut Pmc) -> I2cSlave<Self, PINS>;
+}
+
+macro_rules! i2c_slave {
+    ($TWIX:ident, $twix:ident, $clock:ident) => {
+        impl<SDA, SCL> I2cSlaveExt<(SDA, SCL)> for $TWIX
+        where
+            SDA: SdaPin<$TWIX>,
+            SCL: SclPin<$TWIX>,
+        {
+            fn i2c_slave(self, pins: (SDA, SCL), address: u8, pmc: &mut Pmc) -> I2cSlave<$TWIX, (SDA, SCL)> {
+                assert!(address < 0x80);
+                pmc.enable_clock(PeripheralClock::$clock);
+
+                self.cr.write_with_zero(|w| w.swrst().set_bit());
+                self.rhr.read();
+
+                self.smr.write(|w| unsafe { w.sadr().bits(address) });
+                self.cr.write_with_zero(|w| w.msdis().set_bit().sven().set_bit());
+
+                I2cSlave { twi: self, pins, latched: Latched::default(), in_access: false }
+            }
+        }
+
+        impl<PINS> I2cSlave<$TWIX, PINS> {
+            /// Reads SR, latching the flags the read clears
+            fn status(&mut self) -> crate::pac::$twix::sr::R {
+                let sr = self.twi.sr.read();
+                self.latched.general_call |= sr.gacc().bit_is_set();
+                self.latched.end_of_access |= sr.eosacc().bit_is_set();
+                self.latched.overrun |= sr.ovre().bit_is_set();
+                sr
+            }
+
+            /// Returns the next thing the master did.  Received bytes come
+            /// before the end of the access they belong to.
+            pub fn activity(&mut self) -> nb::Result<Activity, Error> {
+                let sr = self.status();
+
+                if core::mem::replace(&mut self.latched.overrun, false) {
+                    return Err(nb::Error::Other(Error::Overrun));
+                }
+
+                if !self.in_access {
+                    if sr.svacc().bit_is_clear() {
+                        // Only stale flags from an access that's already been reported
+                        self.latched.end_of_access = false;
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.in_access = true;
+
+                    if core::mem::replace(&mut self.latched.general_call, false) {
+                        return Ok(Activity::GeneralCall);
+                    }
+
+                    return Ok(match sr.svread().bit_is_set() {
+                        true => Activity::AddressMatched(Direction::Read),
+                        false => Activity::AddressMatched(Direction::Write),
+                    });
+                }
+
+                if sr.rxrdy().bit_is_set() {
+                    return Ok(Activity::Written(self.twi.rhr.read().rxdata().bits()));
+                }
+
+                if core::mem::replace(&mut self.latched.end_of_access, false) {
+                    self.in_access = false;
+                    return Ok(Activity::Stop);
+                }
+
+                if sr.svacc().bit_is_set() && sr.svread().bit_is_set() && sr.txrdy().bit_is_set() {
+                    return Ok(Activity::Reading);
+                }
+
+                Err(nb::Error::WouldBlock)
+            }
+
+            /// Hands the master the byte it's reading, releasing SCL
+            pub fn transmit(&mut self, byte: u8) {
+                self.twi.thr.write(|w| unsafe { w.txdata().bits(byte) });
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                match event {
+                    Event::AddressMatch => self.twi.ier.write_with_zero(|w| w.svacc().set_bit()),
+                    Event::GeneralCall => self.twi.ier.write_with_zero(|w| w.gacc().set_bit()),
+                    Event::EndOfAccess => self.twi.ier.write_with_zero(|w| w.eosacc().set_bit()),
+                    Event::RxReady => self.twi.ier.write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => self.twi.ier.write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::Overrun => self.twi.ier.write_with_zero(|w| w.ovre().set_bit()),
+                }
+            }
+
+            /// Stops listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                match event {
+                    Event::AddressMatch => self.twi.idr.write_with_zero(|w| w.svacc().set_bit()),
+                    Event::GeneralCall => self.twi.idr.write_with_zero(|w| w.gacc().set_bit()),
+                    Event::EndOfAccess => self.twi.idr.write_with_zero(|w| w.eosacc().set_bit()),
+                    Event::RxReady => self.twi.idr.write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => self.twi.idr.write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::Overrun => self.twi.idr.write_with_zero(|w| w.ovre().set_bit()),
+                }
+            }
+
+            /// Releases the TWI peripheral and pins
+            pub fn free(self) -> ($TWIX, PINS) {
+                self.twi.cr.write_with_zero(|w| w.svdis().set_bit());
+                (self.twi, self.pins)
+            }
+        }
+    };
+}
+
+i2c_slave!(TWI0, twi0, Twi0);
+i2c_slave!(TWI1, twi1, Twi1);
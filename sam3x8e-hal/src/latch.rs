@@ -0,0 +1,54 @@
+/*
+ *    This file (src/latch.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Status flags that clear when their register is read
+//!
+//! Several parts of a driver often poll the same status register, and each
+//! read clears flags the others may be waiting on.  A `Latch` keeps every
+//! such flag seen until the part it belongs to takes it.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub(crate) struct Latch {
+    flags: AtomicU32,
+    /// The register's clear on read flags
+    mask: u32,
+}
+
+impl Latch {
+    pub(crate) const fn new(mask: u32) -> Self {
+        Latch { flags: AtomicU32::new(0), mask }
+    }
+
+    /// Keeps the flags of a fresh register read, returning it along with
+    /// the flags kept from earlier reads
+    pub(crate) fn update(&self, status: u32) -> u32 {
+        self.flags.fetch_or(status & self.mask, Ordering::AcqRel) | status
+    }
+
+    /// Like `update`, then returns and forgets the flags in `mask`
+    pub(crate) fn take(&self, status: u32, mask: u32) -> u32 {
+        let pending = self.update(status);
+        self.flags.fetch_and(!mask, Ordering::AcqRel);
+        pending & mask
+    }
+
+    /// Forgets every kept flag
+    pub(crate) fn clear(&self) {
+        self.flags.store(0, Ordering::Release);
+    }
+}
@@ -50,11 +50,21 @@ extern crate void;
 #[cfg(feature = "sam3x")]
 pub use sam3x8e as pac;
 
+pub mod adc;
+pub mod dacc;
 pub mod delay;
+pub mod dma;
 pub mod efc;
 pub mod gpio;
+pub mod i2c;
+mod latch;
+pub mod pdc;
 pub mod prelude;
+pub mod pwm;
 pub mod rng;
+pub mod serial;
+pub mod spi;
+pub mod ssc;
 pub mod time;
 pub mod timer;
 pub mod pmc;
@@ -0,0 +1,583 @@
+/*
+ *    This file (src/pdc.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Peripheral DMA Controller.  Datasheet §26
+//!
+//! The UART, USARTs, TWIs, ADC, DACC and PWM each carry a PDC channel with a
+//! current and a next pointer / counter pair per direction.  When the current
+//! counter reaches zero the next pair is copied over and the transfer carries
+//! on, which is what `DoubleBuffer` builds on.
+//!
+//! Buffers are handed over to a `Transfer` for as long as the PDC may access
+//! them, so they have to outlive it.  `&'static` references and pinned
+//! `&'static mut` references work out of the box, other owning pointers whose
+//! target never moves (pool boxes for instance) can implement `ReadBuffer` and
+//! `WriteBuffer` themselves.  The counters count transfers, not bytes, and the
+//! width of a transfer depends on the peripheral and its mode.
+
+use core::mem;
+use core::pin::Pin;
+use core::sync::atomic::{self, Ordering};
+
+use crate::pac::{ADC, DACC, PWM, TWI0, TWI1, UART, USART0, USART1, USART2, USART3};
+
+/// Largest number of transfers a single counter can hold
+pub const MAX_TRANSFERS: usize = 0xffff;
+
+/// A unit of transfer the PDC can move in one go
+///
+/// # Safety
+///
+/// The type must be plain data valid for any bit pattern the PDC writes, and
+/// its size must be one of the transfer widths, 1, 2 or 4 bytes.
+pub unsafe trait Word: Copy {}
+
+unsafe impl Word for u8 {}
+unsafe impl Word for u16 {}
+unsafe impl Word for u32 {}
+
+/// Contiguous memory the PDC can be pointed at
+///
+/// # Safety
+///
+/// `as_words` and `as_words_mut` must return the same memory on every call.
+pub unsafe trait Storage {
+    type Word: Word;
+
+    fn as_words(&self) -> &[Self::Word];
+
+    fn as_words_mut(&mut self) -> &mut [Self::Word];
+}
+
+unsafe impl<W: Word> Storage for [W] {
+    type Word = W;
+
+    fn as_words(&self) -> &[W] {
+        self
+    }
+
+    fn as_words_mut(&mut self) -> &mut [W] {
+        self
+    }
+}
+
+unsafe impl<W: Word, const N: usize> Storage for [W; N] {
+    type Word = W;
+
+    fn as_words(&self) -> &[W] {
+        self
+    }
+
+    fn as_words_mut(&mut self) -> &mut [W] {
+        self
+    }
+}
+
+/// A buffer the PDC can read from
+///
+/// # Safety
+///
+/// The memory returned by `read_buffer` must stay put and stay valid for as
+/// long as the buffer value lives, even after it has been moved, and every
+/// call must return the same memory.
+pub unsafe trait ReadBuffer {
+    type Word: Word;
+
+    fn read_buffer(&self) -> (*const Self::Word, usize);
+}
+
+/// A buffer the PDC can write to
+///
+/// # Safety
+///
+/// Same as `ReadBuffer`, and nothing else may access the memory while the
+/// buffer value lives.
+pub unsafe trait WriteBuffer {
+    type Word: Word;
+
+    fn write_buffer(&mut self) -> (*mut Self::Word, usize);
+}
+
+unsafe impl<T: Storage + ?Sized> ReadBuffer for &'static T {
+    type Word = T::Word;
+
+    fn read_buffer(&self) -> (*const T::Word, usize) {
+        let words = self.as_words();
+        (words.as_ptr(), words.len())
+    }
+}
+
+unsafe impl<T: Storage + ?Sized> ReadBuffer for &'static mut T {
+    type Word = T::Word;
+
+    fn read_buffer(&self) -> (*const T::Word, usize) {
+        let words = self.as_words();
+        (words.as_ptr(), words.len())
+    }
+}
+
+unsafe impl<T: Storage + ?Sized> WriteBuffer for &'static mut T {
+    type Word = T::Word;
+
+    fn write_buffer(&mut self) -> (*mut T::Word, usize) {
+        let words = self.as_words_mut();
+        (words.as_mut_ptr(), words.len())
+    }
+}
+
+unsafe impl<T: Storage + ?Sized> ReadBuffer for Pin<&'static mut T> {
+    type Word = T::Word;
+
+    fn read_buffer(&self) -> (*const T::Word, usize) {
+        let words = self.as_ref().get_ref().as_words();
+        (words.as_ptr(), words.len())
+    }
+}
+
+unsafe impl<T: Storage + Unpin + ?Sized> WriteBuffer for Pin<&'static mut T> {
+    type Word = T::Word;
+
+    fn write_buffer(&mut self) -> (*mut T::Word, usize) {
+        let words = self.as_mut().get_mut().as_words_mut();
+        (words.as_mut_ptr(), words.len())
+    }
+}
+
+/// Raw access to the receive half of a PDC channel
+pub trait PdcRx {
+    /// Points the current receive pair at `count` transfers starting at `address`
+    ///
+    /// # Safety
+    ///
+    /// `count` transfers of the peripheral's width at `address` must stay
+    /// valid, and not be otherwise accessed, until the PDC is done with them.
+    unsafe fn set_rx(&self, address: u32, count: u16);
+
+    /// Points the next receive pair at `count` transfers starting at `address`
+    ///
+    /// # Safety
+    ///
+    /// As for `set_rx`.
+    unsafe fn set_rx_next(&self, address: u32, count: u16);
+
+    /// Transfers left in the current receive pair
+    fn rx_remaining(&self) -> u16;
+
+    /// Transfers left in the next receive pair
+    fn rx_next_remaining(&self) -> u16;
+
+    fn enable_rx(&self);
+
+    fn disable_rx(&self);
+
+    fn is_rx_enabled(&self) -> bool;
+}
+
+/// Raw access to the transmit half of a PDC channel
+pub trait PdcTx {
+    /// Points the current transmit pair at `count` transfers starting at `address`
+    ///
+    /// # Safety
+    ///
+    /// `count` transfers of the peripheral's width at `address` must stay
+    /// valid, and not be otherwise accessed, until the PDC is done with them.
+    unsafe fn set_tx(&self, address: u32, count: u16);
+
+    /// Points the next transmit pair at `count` transfers starting at `address`
+    ///
+    /// # Safety
+    ///
+    /// As for `set_tx`.
+    unsafe fn set_tx_next(&self, address: u32, count: u16);
+
+    /// Transfers left in the current transmit pair
+    fn tx_remaining(&self) -> u16;
+
+    /// Transfers left in the next transmit pair
+    fn tx_next_remaining(&self) -> u16;
+
+    fn enable_tx(&self);
+
+    fn disable_tx(&self);
+
+    fn is_tx_enabled(&self) -> bool;
+}
+
+macro_rules! pdc_rx {
+    ($($PERIPH:ident),+) => {
+        $(
+            impl PdcRx for $PERIPH {
+                unsafe fn set_rx(&self, address: u32, count: u16) {
+                    self.rpr.write(|w| w.rxptr().bits(address));
+                    self.rcr.write(|w| w.rxctr().bits(count));
+                }
+
+                unsafe fn set_rx_next(&self, address: u32, count: u16) {
+                    self.rnpr.write(|w| w.rxnptr().bits(address));
+                    self.rncr.write(|w| w.rxnctr().bits(count));
+                }
+
+                fn rx_remaining(&self) -> u16 {
+                    self.rcr.read().rxctr().bits()
+                }
+
+                fn rx_next_remaining(&self) -> u16 {
+                    self.rncr.read().rxnctr().bits()
+                }
+
+                fn enable_rx(&self) {
+                    self.ptcr.write(|w| w.rxten().set_bit());
+                }
+
+                fn disable_rx(&self) {
+                    self.ptcr.write(|w| w.rxtdis().set_bit());
+                }
+
+                fn is_rx_enabled(&self) -> bool {
+                    self.ptsr.read().rxten().bit_is_set()
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pdc_tx {
+    ($($PERIPH:ty),+) => {
+        $(
+            impl PdcTx for $PERIPH {
+                unsafe fn set_tx(&self, address: u32, count: u16) {
+                    self.tpr.write(|w| w.txptr().bits(address));
+                    self.tcr.write(|w| w.txctr().bits(count));
+                }
+
+                unsafe fn set_tx_next(&self, address: u32, count: u16) {
+                    self.tnpr.write(|w| w.txnptr().bits(address));
+                    self.tncr.write(|w| w.txnctr().bits(count));
+                }
+
+                fn tx_remaining(&self) -> u16 {
+                    self.tcr.read().txctr().bits()
+                }
+
+                fn tx_next_remaining(&self) -> u16 {
+                    self.tncr.read().txnctr().bits()
+                }
+
+                fn enable_tx(&self) {
+                    self.ptcr.write(|w| w.txten().set_bit());
+                }
+
+                fn disable_tx(&self) {
+                    self.ptcr.write(|w| w.txtdis().set_bit());
+                }
+
+                fn is_tx_enabled(&self) -> bool {
+                    self.ptsr.read().txten().bit_is_set()
+                }
+            }
+        )+
+    };
+}
+
+pdc_rx!(ADC, TWI0, TWI1, UART, USART0, USART1, USART2, USART3);
+pdc_tx!(DACC, PWM, TWI0, TWI1, UART, USART0, USART1, USART2, USART3);
+// Lets the PWM synchronous channel handle stream without owning the PWM
+pdc_tx!(crate::pac::pwm::RegisterBlock);
+
+/// A driver that can receive through its PDC channel
+pub trait Receive: Sized {
+    /// Width of a single transfer
+    type Word: Word;
+    type Channel: PdcRx;
+
+    fn rx_channel(&self) -> &Self::Channel;
+
+    /// Receives until `buffer` is full
+    fn read_all<B>(self, mut buffer: B) -> Transfer<Self, B, Rx>
+    where
+        B: WriteBuffer<Word = Self::Word>,
+    {
+        let (address, count) = buffer.write_buffer();
+        assert!(count <= MAX_TRANSFERS);
+
+        let channel = self.rx_channel();
+        channel.disable_rx();
+        unsafe {
+            channel.set_rx(address as u32, count as u16);
+            channel.set_rx_next(0, 0);
+        }
+
+        // The buffer has to be handed over before the PDC touches it
+        atomic::compiler_fence(Ordering::Release);
+        channel.enable_rx();
+
+        Transfer { peripheral: self, buffer, _direction: Rx }
+    }
+
+    /// Receives into `first` then `second` without a gap, see `DoubleBuffer::swap`
+    fn read_double<B>(self, mut first: B, mut second: B) -> DoubleBuffer<Self, B, Rx>
+    where
+        B: WriteBuffer<Word = Self::Word>,
+    {
+        let (first_address, first_count) = first.write_buffer();
+        let (second_address, second_count) = second.write_buffer();
+        assert!(first_count > 0 && first_count <= MAX_TRANSFERS);
+        assert!(second_count > 0 && second_count <= MAX_TRANSFERS);
+
+        let channel = self.rx_channel();
+        channel.disable_rx();
+        unsafe {
+            channel.set_rx(first_address as u32, first_count as u16);
+            channel.set_rx_next(second_address as u32, second_count as u16);
+        }
+
+        atomic::compiler_fence(Ordering::Release);
+        channel.enable_rx();
+
+        DoubleBuffer { peripheral: self, current: first, next: second, overrun: false, _direction: Rx }
+    }
+}
+
+/// A driver that can transmit through its PDC channel
+pub trait Transmit: Sized {
+    /// Width of a single transfer
+    type Word: Word;
+    type Channel: PdcTx;
+
+    fn tx_channel(&self) -> &Self::Channel;
+
+    /// Sends the whole of `buffer`
+    fn write_all<B>(self, buffer: B) -> Transfer<Self, B, Tx>
+    where
+        B: ReadBuffer<Word = Self::Word>,
+    {
+        let (address, count) = buffer.read_buffer();
+        assert!(count <= MAX_TRANSFERS);
+
+        let channel = self.tx_channel();
+        channel.disable_tx();
+
+        // Any writes to the buffer have to land before the PDC reads it
+        atomic::compiler_fence(Ordering::Release);
+        unsafe {
+            channel.set_tx(address as u32, count as u16);
+            channel.set_tx_next(0, 0);
+        }
+        channel.enable_tx();
+
+        Transfer { peripheral: self, buffer, _direction: Tx }
+    }
+
+    /// Sends `first` then `second` without a gap, see `DoubleBuffer::swap`
+    fn write_double<B>(self, first: B, second: B) -> DoubleBuffer<Self, B, Tx>
+    where
+        B: ReadBuffer<Word = Self::Word>,
+    {
+        let (first_address, first_count) = first.read_buffer();
+        let (second_address, second_count) = second.read_buffer();
+        assert!(first_count > 0 && first_count <= MAX_TRANSFERS);
+        assert!(second_count > 0 && second_count <= MAX_TRANSFERS);
+
+        let channel = self.tx_channel();
+        channel.disable_tx();
+
+        atomic::compiler_fence(Ordering::Release);
+        unsafe {
+            channel.set_tx(first_address as u32, first_count as u16);
+            channel.set_tx_next(second_address as u32, second_count as u16);
+        }
+        channel.enable_tx();
+
+        DoubleBuffer { peripheral: self, current: first, next: second, overrun: false, _direction: Tx }
+    }
+}
+
+/// Peripheral to memory
+pub struct Rx;
+
+/// Memory to peripheral
+pub struct Tx;
+
+/// A single buffer transfer in progress.  The buffer and the driver are
+/// handed back once the transfer is over.
+pub struct Transfer<PERIPH, BUF, DIR> {
+    peripheral: PERIPH,
+    buffer: BUF,
+    _direction: DIR,
+}
+
+impl<PERIPH: Receive, BUF> Transfer<PERIPH, BUF, Rx> {
+    pub fn is_done(&self) -> bool {
+        self.peripheral.rx_channel().rx_remaining() == 0
+    }
+
+    /// Number of transfers still to be received
+    pub fn remaining(&self) -> usize {
+        self.peripheral.rx_channel().rx_remaining() as usize
+    }
+
+    /// Blocks until the buffer is full
+    pub fn wait(self) -> (PERIPH, BUF) {
+        while !self.is_done() {}
+        self.stop()
+    }
+
+    /// Stops receiving, the buffer is left partly filled.  See `remaining`.
+    pub fn stop(self) -> (PERIPH, BUF) {
+        self.peripheral.rx_channel().disable_rx();
+        atomic::compiler_fence(Ordering::Acquire);
+        (self.peripheral, self.buffer)
+    }
+}
+
+impl<PERIPH: Transmit, BUF> Transfer<PERIPH, BUF, Tx> {
+    /// The PDC has handed the whole buffer to the peripheral, which may still
+    /// be shifting out the last of it
+    pub fn is_done(&self) -> bool {
+        self.peripheral.tx_channel().tx_remaining() == 0
+    }
+
+    /// Number of transfers still to be sent
+    pub fn remaining(&self) -> usize {
+        self.peripheral.tx_channel().tx_remaining() as usize
+    }
+
+    /// Blocks until the whole buffer has been handed to the peripheral
+    pub fn wait(self) -> (PERIPH, BUF) {
+        while !self.is_done() {}
+        self.stop()
+    }
+
+    /// Stops sending
+    pub fn stop(self) -> (PERIPH, BUF) {
+        self.peripheral.tx_channel().disable_tx();
+        atomic::compiler_fence(Ordering::Acquire);
+        (self.peripheral, self.buffer)
+    }
+}
+
+/// A continuous transfer through two buffers: while the PDC works through one
+/// the other is handed back to be emptied or refilled and queued again.
+pub struct DoubleBuffer<PERIPH, BUF, DIR> {
+    peripheral: PERIPH,
+    current: BUF,
+    next: BUF,
+    overrun: bool,
+    _direction: DIR,
+}
+
+impl<PERIPH, BUF> DoubleBuffer<PERIPH, BUF, Rx>
+where
+    PERIPH: Receive,
+    BUF: WriteBuffer<Word = PERIPH::Word>,
+{
+    /// The PDC has filled the current buffer and moved on to the next one
+    pub fn is_ready(&self) -> bool {
+        self.peripheral.rx_channel().rx_next_remaining() == 0
+    }
+
+    /// Hands back the buffer the PDC has just filled and queues `buffer` to be
+    /// filled after the one in progress
+    pub fn swap(&mut self, mut buffer: BUF) -> nb::Result<BUF, void::Void> {
+        let channel = self.peripheral.rx_channel();
+
+        if channel.rx_next_remaining() != 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        atomic::compiler_fence(Ordering::Acquire);
+
+        let (address, count) = buffer.write_buffer();
+        assert!(count > 0 && count <= MAX_TRANSFERS);
+
+        // Both buffers are full and the PDC has stopped, restart it with this
+        // one.  The buffer it was working on is handed back on the next swap.
+        if channel.rx_remaining() == 0 {
+            self.overrun = true;
+            unsafe { channel.set_rx(address as u32, count as u16) };
+        } else {
+            unsafe { channel.set_rx_next(address as u32, count as u16) };
+        }
+
+        let next = mem::replace(&mut self.next, buffer);
+        Ok(mem::replace(&mut self.current, next))
+    }
+
+    /// Returns and clears whether data was dropped because both buffers were
+    /// full at some point
+    pub fn overrun(&mut self) -> bool {
+        mem::replace(&mut self.overrun, false)
+    }
+
+    /// Stops receiving and hands back both buffers, the one being filled last
+    pub fn stop(self) -> (PERIPH, BUF, BUF) {
+        self.peripheral.rx_channel().disable_rx();
+        atomic::compiler_fence(Ordering::Acquire);
+        (self.peripheral, self.current, self.next)
+    }
+}
+
+impl<PERIPH, BUF> DoubleBuffer<PERIPH, BUF, Tx>
+where
+    PERIPH: Transmit,
+    BUF: ReadBuffer<Word = PERIPH::Word>,
+{
+    /// The PDC has sent the current buffer and moved on to the next one
+    pub fn is_ready(&self) -> bool {
+        self.peripheral.tx_channel().tx_next_remaining() == 0
+    }
+
+    /// Hands back the buffer the PDC has just sent and queues `buffer` to be
+    /// sent after the one in progress
+    pub fn swap(&mut self, buffer: BUF) -> nb::Result<BUF, void::Void> {
+        let channel = self.peripheral.tx_channel();
+
+        if channel.tx_next_remaining() != 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let (address, count) = buffer.read_buffer();
+        assert!(count > 0 && count <= MAX_TRANSFERS);
+
+        atomic::compiler_fence(Ordering::Release);
+
+        // Both buffers have been sent and the PDC has stopped, restart it with
+        // this one.  The buffer it was working on is handed back on the next swap.
+        if channel.tx_remaining() == 0 {
+            self.overrun = true;
+            unsafe { channel.set_tx(address as u32, count as u16) };
+        } else {
+            unsafe { channel.set_tx_next(address as u32, count as u16) };
+        }
+
+        let next = mem::replace(&mut self.next, buffer);
+        Ok(mem::replace(&mut self.current, next))
+    }
+
+    /// Returns and clears whether the output stalled because both buffers had
+    /// been sent at some point
+    pub fn underrun(&mut self) -> bool {
+        mem::replace(&mut self.overrun, false)
+    }
+
+    /// Stops sending and hands back both buffers, the one being sent first
+    pub fn stop(self) -> (PERIPH, BUF, BUF) {
+        self.peripheral.tx_channel().disable_tx();
+        atomic::compiler_fence(Ordering::Acquire);
+        (self.peripheral, self.current, self.next)
+    }
+}
@@ -221,6 +221,7 @@ pub enum PeripheralClock {
     PioB,
     PioC,
     PioD,
+    Uart,
     Usart0,
     Usart1,
     Usart2,
@@ -265,6 +266,7 @@ impl Pmc {
             PeripheralClock::PioB => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid12().set_bit()),
             PeripheralClock::PioC => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid13().set_bit()),
             PeripheralClock::PioD => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid14().set_bit()),
+            PeripheralClock::Uart => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid8().set_bit()),
             PeripheralClock::Usart0 => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid17().set_bit()),
             PeripheralClock::Usart1 => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid18().set_bit()),
             PeripheralClock::Usart2 => self.pmc.pmc_pcer0.write_with_zero(|w| w.pid19().set_bit()),
@@ -309,6 +311,7 @@ impl Pmc {
             PeripheralClock::PioB => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid12().set_bit()),
             PeripheralClock::PioC => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid13().set_bit()),
             PeripheralClock::PioD => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid14().set_bit()),
+            PeripheralClock::Uart => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid8().set_bit()),
             PeripheralClock::Usart0 => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid17().set_bit()),
             PeripheralClock::Usart1 => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid18().set_bit()),
             PeripheralClock::Usart2 => self.pmc.pmc_pcdr0.write_with_zero(|w| w.pid19().set_bit()),
@@ -17,4 +17,4 @@
 
 pub use embedded_hal::{digital::v2::*, prelude::*};
 
-pub use crate::{delay::*, efc::{Config as EfcConfig, Efc0Ext, Efc1Ext}, gpio::GpioExt as _, time::U32Ext as _, timer::TimerExt as _, pmc::PmcExt as _, watchdog::WatchdogExt as _};
+pub use crate::{adc::AdcExt as _, dacc::DaccExt as _, delay::*, dma::DmacExt as _, efc::{Config as EfcConfig, Efc0Ext, Efc1Ext}, gpio::GpioExt as _, i2c::{slave::I2cSlaveExt as _, I2cExt as _}, time::U32Ext as _, timer::{TimerCounterExt as _, TimerExt as _}, pdc::{Receive as _, Transmit as _}, pmc::PmcExt as _, pwm::PwmExt as _, serial::{irda::IrDAExt as _, iso7816::Iso7816Ext as _, lin::LinExt as _, manchester::ManchesterExt as _, uart::UartExt as _, usart::UsartExt as _}, spi::{slave::SpiSlaveExt as _, SpiExt as _}, ssc::SscExt as _, watchdog::WatchdogExt as _};
@@ -0,0 +1,731 @@
+/*
+ *    This file (src/pwm.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Pulse Width Modulation controller.  Datasheet §38
+//!
+//! The controller has eight channels, each with its own counter, period and
+//! duty cycle, driving a PWMH output and its complement on PWML.  Channel
+//! counters run from the master clock through a power of two prescaler or
+//! from one of the two divided clocks, CLKA and CLKB.
+//!
+//! Duty cycle and period changes on a running channel go through the update
+//! registers and take effect at the start of the next period, so the output
+//! never glitches.
+//!
+//! For half bridges the dead time generator delays the rising edge of each
+//! output of a pair so the two switches are never on together, and the output
+//! override forces either output to a fixed level.  The fault unit in `fault`
+//! drives chosen outputs to safe levels in hardware when a fault input trips.
+//! Channels made synchronous through `sync` share channel 0's counter and can
+//! have their duty cycles streamed in by the PDC.  The comparison units in
+//! `compare` match on channel 0's counter and pulse the event lines that
+//! trigger ADC conversions.
+
+use core::marker::PhantomData;
+
+use crate::gpio::PeripheralB;
+use crate::gpio::pioa::{PA8, PA9, PA12, PA13, PA19, PA20, PA21};
+use crate::gpio::piob::{PB12, PB13, PB14, PB15, PB16, PB17, PB18, PB19};
+use crate::gpio::pioc::{PC2, PC3, PC4, PC5, PC6, PC7, PC8, PC9, PC18, PC19, PC20, PC21, PC22, PC23, PC24};
+#[cfg(feature = "unproven")]
+use crate::hal;
+use crate::latch::Latch;
+use crate::pac::PWM;
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::time::{Hertz, U32Ext};
+
+pub mod compare;
+pub mod fault;
+pub mod sync;
+
+/// Largest period and duty cycle, in counter clocks
+pub const MAX_PERIOD: u32 = 0x00ff_ffff;
+
+/// Channel counter clock
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Clock {
+    /// MCK / 2^n, n from 0 to 10
+    Prescaled(u8),
+    /// Divided clock A
+    ClkA,
+    /// Divided clock B
+    ClkB,
+}
+
+impl Clock {
+    /// CPRE value
+    fn bits(self) -> u8 {
+        match self {
+            Clock::Prescaled(shift) => shift,
+            Clock::ClkA => 11,
+            Clock::ClkB => 12,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Alignment {
+    /// The counter runs up and restarts, the output changes once per period
+    Left,
+    /// The counter runs up then down, the output is symmetric around the
+    /// middle of the period
+    Center,
+}
+
+/// Level of PWMH during the duty cycle, PWML is the complement
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// One output of a channel's complementary pair
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Output {
+    /// PWMHx
+    High,
+    /// PWMLx
+    Low,
+}
+
+/// When an output override change takes effect
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Update {
+    Immediate,
+    /// At the start of the next period
+    NextPeriod,
+}
+
+/// Channel identifiers
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChannelId {
+    C0,
+    C1,
+    C2,
+    C3,
+    C4,
+    C5,
+    C6,
+    C7,
+}
+
+impl ChannelId {
+    fn index(self) -> u32 {
+        match self {
+            ChannelId::C0 => 0,
+            ChannelId::C1 => 1,
+            ChannelId::C2 => 2,
+            ChannelId::C3 => 3,
+            ChannelId::C4 => 4,
+            ChannelId::C5 => 5,
+            ChannelId::C6 => 6,
+            ChannelId::C7 => 7,
+        }
+    }
+}
+
+pub struct C0;
+pub struct C1;
+pub struct C2;
+pub struct C3;
+pub struct C4;
+pub struct C5;
+pub struct C6;
+pub struct C7;
+
+/// A PWMHx output.  `()` leaves it unused.
+pub trait HighPin<CHANNEL> {}
+/// A PWMLx output.  `()` leaves it unused.
+pub trait LowPin<CHANNEL> {}
+
+impl<CHANNEL> HighPin<CHANNEL> for () {}
+impl<CHANNEL> LowPin<CHANNEL> for () {}
+
+impl HighPin<C0> for PA8<PeripheralB> {}
+impl HighPin<C0> for PB12<PeripheralB> {}
+impl HighPin<C0> for PC3<PeripheralB> {}
+impl HighPin<C1> for PA19<PeripheralB> {}
+impl HighPin<C1> for PB13<PeripheralB> {}
+impl HighPin<C1> for PC5<PeripheralB> {}
+impl HighPin<C2> for PA13<PeripheralB> {}
+impl HighPin<C2> for PB14<PeripheralB> {}
+impl HighPin<C2> for PC7<PeripheralB> {}
+impl HighPin<C3> for PA9<PeripheralB> {}
+impl HighPin<C3> for PB15<PeripheralB> {}
+impl HighPin<C3> for PC9<PeripheralB> {}
+impl HighPin<C4> for PC20<PeripheralB> {}
+impl HighPin<C5> for PC19<PeripheralB> {}
+impl HighPin<C6> for PC18<PeripheralB> {}
+
+impl LowPin<C0> for PA21<PeripheralB> {}
+impl LowPin<C0> for PB16<PeripheralB> {}
+impl LowPin<C0> for PC2<PeripheralB> {}
+impl LowPin<C1> for PA12<PeripheralB> {}
+impl LowPin<C1> for PB17<PeripheralB> {}
+impl LowPin<C1> for PC4<PeripheralB> {}
+impl LowPin<C2> for PA20<PeripheralB> {}
+impl LowPin<C2> for PB18<PeripheralB> {}
+impl LowPin<C2> for PC6<PeripheralB> {}
+impl LowPin<C3> for PB19<PeripheralB> {}
+impl LowPin<C3> for PC8<PeripheralB> {}
+impl LowPin<C4> for PC21<PeripheralB> {}
+impl LowPin<C5> for PC22<PeripheralB> {}
+impl LowPin<C6> for PC23<PeripheralB> {}
+impl LowPin<C7> for PC24<PeripheralB> {}
+
+/// Controller configuration
+#[derive(Default)]
+pub struct Config {
+    clka: Option<Hertz>,
+    clkb: Option<Hertz>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// Runs CLKA as close to `frequency` as the dividers allow
+    pub fn clka<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        self.clka = Some(frequency.into());
+        self
+    }
+
+    /// Runs CLKB as close to `frequency` as the dividers allow
+    pub fn clkb<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        self.clkb = Some(frequency.into());
+        self
+    }
+}
+
+/// Channel configuration
+#[derive(Clone, Copy)]
+pub struct ChannelConfig {
+    clock: Clock,
+    alignment: Alignment,
+    polarity: Polarity,
+    frequency: Hertz,
+    dead_time: Option<(u16, u16)>,
+    invert_high: bool,
+    invert_low: bool,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> ChannelConfig {
+        ChannelConfig {
+            clock: Clock::Prescaled(0),
+            alignment: Alignment::Left,
+            polarity: Polarity::ActiveHigh,
+            frequency: 1_000.hz(),
+            dead_time: None,
+            invert_high: false,
+            invert_low: false,
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn new() -> Self {
+        ChannelConfig {..Self::default()}
+    }
+
+    pub fn clock(mut self, clock: Clock) -> Self {
+        if let Clock::Prescaled(shift) = clock {
+            assert!(shift <= 10);
+        }
+        self.clock = clock;
+        self
+    }
+
+    pub fn alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn polarity(mut self, polarity: Polarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// Output frequency, rounded to a whole number of counter clocks
+    pub fn frequency<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        self.frequency = frequency.into();
+        self
+    }
+
+    /// Turns on the dead time generator.  PWMH rises `high` counter clocks
+    /// late and PWML `low` counter clocks late.  `high` must stay below the
+    /// period minus the duty cycle and `low` below the duty cycle.
+    pub fn dead_time(mut self, high: u16, low: u16) -> Self {
+        self.dead_time = Some((high, low));
+        self
+    }
+
+    /// Inverts PWMH after the dead time generator
+    pub fn invert_high(mut self, invert: bool) -> Self {
+        self.invert_high = invert;
+        self
+    }
+
+    /// Inverts PWML after the dead time generator
+    pub fn invert_low(mut self, invert: bool) -> Self {
+        self.invert_low = invert;
+        self
+    }
+}
+
+/// ISR1 flags, CHIDx and FCHIDx, shared by the channel counter events and
+/// the fault unit
+static ISR1: Latch = Latch::new(0x00ff_00ff);
+
+/// Returns and clears the ISR1 flags in `mask`
+fn take_isr1(mask: u32) -> u32 {
+    ISR1.take(unsafe { (*PWM::ptr()).isr1.read().bits() }, mask)
+}
+
+/// ISR2 flags, WRDY, UNRE, CMPMx and CMPUx, shared by the synchronous
+/// channels and the comparison units
+static ISR2: Latch = Latch::new(0x00ff_ff09);
+
+/// Returns and clears the ISR2 flags in `mask`
+fn take_isr2(mask: u32) -> u32 {
+    ISR2.take(unsafe { (*PWM::ptr()).isr2.read().bits() }, mask)
+}
+
+/// Works out PREx / DIVx for a divided clock.  The clock runs at
+/// MCK / 2^PREx / DIVx.
+fn divided_clock(clocks: &Clocks, frequency: Hertz) -> (u8, u8) {
+    let master_clk = clocks.master_clk().0;
+    assert!(frequency.0 > 0);
+
+    (0..=10)
+        .map(|pre| (pre, ((master_clk >> pre) + frequency.0 / 2) / frequency.0))
+        .find(|&(_, div)| div <= 0xff)
+        .map(|(pre, div)| (pre, div.max(1) as u8))
+        .expect("divided clock too slow")
+}
+
+/// Counter clock frequency for a CPRE value, zero if it's a divided clock
+/// that's turned off
+fn counter_clock(clocks: &Clocks, cpre: u8) -> u32 {
+    let master_clk = clocks.master_clk().0;
+    let clk = unsafe { (*PWM::ptr()).clk.read() };
+
+    let (pre, div) = match cpre {
+        11 => (clk.prea().bits(), clk.diva().bits()),
+        12 => (clk.preb().bits(), clk.divb().bits()),
+        shift => return master_clk >> shift,
+    };
+
+    match div {
+        0 => 0,
+        div => (master_clk >> pre) / div as u32,
+    }
+}
+
+/// Counter clocks in a period, the counter runs both ways when center aligned
+fn period_for(clock: u32, frequency: Hertz, center: bool) -> u32 {
+    let edges = if center { 2 } else { 1 };
+    let period = clock / (frequency.0 * edges);
+    assert!((1..=MAX_PERIOD).contains(&period));
+    period
+}
+
+/// PWM controller
+pub struct Pwm {
+    pwm: PWM,
+    clocks: Clocks,
+}
+
+/// A PWM channel, driving the `PINS` outputs, `(PWMH, PWML)`
+pub struct Channel<CHANNEL, PINS = ((), ())> {
+    clocks: Clocks,
+    pins: PINS,
+    _channel: PhantomData<CHANNEL>,
+}
+
+/// All eight channels, disabled
+pub struct Channels {
+    pub ch0: Channel<C0>,
+    pub ch1: Channel<C1>,
+    pub ch2: Channel<C2>,
+    pub ch3: Channel<C3>,
+    pub ch4: Channel<C4>,
+    pub ch5: Channel<C5>,
+    pub ch6: Channel<C6>,
+    pub ch7: Channel<C7>,
+}
+
+pub trait PwmExt {
+    /// Sets up the divided clocks and hands out the channels, each configured
+    /// with the default `ChannelConfig`
+    fn pwm(self, config: Config, pmc: &mut Pmc) -> (Pwm, Channels);
+}
+
+impl PwmExt for PWM {
+    fn pwm(self, config: Config, pmc: &mut Pmc) -> (Pwm, Channels) {
+        pmc.enable_clock(PeripheralClock::Pwm);
+        let clocks = pmc.clocks;
+
+        self.dis.write_with_zero(|w| unsafe { w.bits(0xff) });
+
+        let (prea, diva) = config.clka.map_or((0, 0), |f| divided_clock(&clocks, f));
+        let (preb, divb) = config.clkb.map_or((0, 0), |f| divided_clock(&clocks, f));
+        self.clk.write(|w| unsafe { w.prea().bits(prea).diva().bits(diva).preb().bits(preb).divb().bits(divb) });
+
+        let mut channels = Channels {
+            ch0: Channel::new(clocks),
+            ch1: Channel::new(clocks),
+            ch2: Channel::new(clocks),
+            ch3: Channel::new(clocks),
+            ch4: Channel::new(clocks),
+            ch5: Channel::new(clocks),
+            ch6: Channel::new(clocks),
+            ch7: Channel::new(clocks),
+        };
+
+        let config = ChannelConfig::default();
+        channels.ch0.configure(&config);
+        channels.ch1.configure(&config);
+        channels.ch2.configure(&config);
+        channels.ch3.configure(&config);
+        channels.ch4.configure(&config);
+        channels.ch5.configure(&config);
+        channels.ch6.configure(&config);
+        channels.ch7.configure(&config);
+
+        (Pwm { pwm: self, clocks }, channels)
+    }
+}
+
+impl Pwm {
+    /// Actual CLKA frequency, zero when it's off
+    pub fn clka(&self) -> Hertz {
+        Hertz(counter_clock(&self.clocks, Clock::ClkA.bits()))
+    }
+
+    /// Actual CLKB frequency, zero when it's off
+    pub fn clkb(&self) -> Hertz {
+        Hertz(counter_clock(&self.clocks, Clock::ClkB.bits()))
+    }
+
+    /// Stops every channel and releases the PWM peripheral
+    pub fn free(self, _channels: Channels) -> PWM {
+        self.pwm.dis.write_with_zero(|w| unsafe { w.bits(0xff) });
+        self.pwm
+    }
+}
+
+impl<CHANNEL> Channel<CHANNEL> {
+    fn new(clocks: Clocks) -> Self {
+        Channel { clocks, pins: ((), ()), _channel: PhantomData }
+    }
+}
+
+/// Override bit of an output.  OOV, OS and its set / clear registers all hold
+/// PWMH0-7 in bits 0-7 and PWML0-7 in bits 16-23.
+fn override_mask(index: u32, output: Output) -> u32 {
+    match output {
+        Output::High => 1 << index,
+        Output::Low => 1 << (index + 16),
+    }
+}
+
+fn override_output(mask: u32, level: bool, update: Update) {
+    let pwm = unsafe { &*PWM::ptr() };
+
+    // OOV is shared by all channels
+    cortex_m::interrupt::free(|_| {
+        pwm.oov.modify(|r, w| unsafe {
+            match level {
+                true => w.bits(r.bits() | mask),
+                false => w.bits(r.bits() & !mask),
+            }
+        })
+    });
+
+    match update {
+        Update::Immediate => pwm.oss.write_with_zero(|w| unsafe { w.bits(mask) }),
+        Update::NextPeriod => pwm.ossupd.write_with_zero(|w| unsafe { w.bits(mask) }),
+    }
+}
+
+fn release_output(mask: u32, update: Update) {
+    let pwm = unsafe { &*PWM::ptr() };
+
+    match update {
+        Update::Immediate => pwm.osc.write_with_zero(|w| unsafe { w.bits(mask) }),
+        Update::NextPeriod => pwm.oscupd.write_with_zero(|w| unsafe { w.bits(mask) }),
+    }
+}
+
+macro_rules! channel {
+    ($CX:ident, $index:expr, $chid:ident, $cmr:ident, $cdty:ident, $cdtyupd:ident, $cprd:ident, $cprdupd:ident, $ccnt:ident, $dt:ident, $dtupd:ident) => {
+        impl<PINS> Channel<$CX, PINS> {
+            fn pwm(&self) -> &crate::pac::pwm::RegisterBlock {
+                unsafe { &*PWM::ptr() }
+            }
+
+            /// Sets the counter clock, alignment, polarity, frequency and dead
+            /// time.  The channel is disabled and the duty cycle reset to zero.
+            pub fn configure(&mut self, config: &ChannelConfig) {
+                self.disable();
+
+                let center = config.alignment == Alignment::Center;
+                let clock = counter_clock(&self.clocks, config.clock.bits());
+                let period = period_for(clock, config.frequency, center);
+
+                self.pwm().$cmr.write(|w| unsafe {
+                    w.cpre().bits(config.clock.bits())
+                        .calg().bit(center)
+                        .cpol().bit(config.polarity == Polarity::ActiveHigh)
+                        .dte().bit(config.dead_time.is_some())
+                        .dthi().bit(config.invert_high)
+                        .dtli().bit(config.invert_low)
+                });
+
+                let (high, low) = config.dead_time.unwrap_or((0, 0));
+                self.pwm().$dt.write(|w| unsafe { w.dth().bits(high).dtl().bits(low) });
+                self.pwm().$cprd.write(|w| unsafe { w.cprd().bits(period) });
+                self.pwm().$cdty.write(|w| unsafe { w.cdty().bits(0) });
+            }
+
+            /// Binds the output pins, `()` for an unused one
+            pub fn with_pins<H, L>(self, pins: (H, L)) -> Channel<$CX, (H, L)>
+            where
+                H: HighPin<$CX>,
+                L: LowPin<$CX>,
+            {
+                Channel { clocks: self.clocks, pins, _channel: PhantomData }
+            }
+
+            /// Releases the output pins
+            pub fn release(self) -> (Channel<$CX>, PINS) {
+                (Channel::new(self.clocks), self.pins)
+            }
+
+            pub fn enable(&mut self) {
+                self.pwm().ena.write_with_zero(|w| w.$chid().set_bit());
+            }
+
+            /// Stops the counter, the outputs go back to their idle levels
+            pub fn disable(&mut self) {
+                self.pwm().dis.write_with_zero(|w| w.$chid().set_bit());
+            }
+
+            pub fn is_enabled(&self) -> bool {
+                self.pwm().sr.read().$chid().bit_is_set()
+            }
+
+            /// Whether the counter reached the end of a period since the last
+            /// call.  CHIDx in ISR1
+            pub fn take_period_end(&mut self) -> bool {
+                take_isr1(1 << $index) != 0
+            }
+
+            fn is_center_aligned(&self) -> bool {
+                self.pwm().$cmr.read().calg().bit_is_set()
+            }
+
+            /// Counter clocks in a period, also the largest duty cycle
+            pub fn period(&self) -> u32 {
+                self.pwm().$cprd.read().cprd().bits()
+            }
+
+            /// Sets the period in counter clocks.  A running channel switches
+            /// over at the start of the next period.
+            pub fn set_period(&mut self, period: u32) {
+                assert!((1..=MAX_PERIOD).contains(&period));
+
+                match self.is_enabled() {
+                    true => self.pwm().$cprdupd.write_with_zero(|w| unsafe { w.cprdupd().bits(period) }),
+                    false => self.pwm().$cprd.write(|w| unsafe { w.cprd().bits(period) }),
+                }
+            }
+
+            /// Output frequency
+            pub fn frequency(&self) -> Hertz {
+                let clock = counter_clock(&self.clocks, self.pwm().$cmr.read().cpre().bits());
+                let edges = if self.is_center_aligned() { 2 } else { 1 };
+                Hertz(clock / (self.period() * edges))
+            }
+
+            /// Changes the output frequency, scaling the duty cycle to keep
+            /// the same ratio
+            pub fn set_frequency<F: Into<Hertz>>(&mut self, frequency: F) {
+                let clock = counter_clock(&self.clocks, self.pwm().$cmr.read().cpre().bits());
+                let period = period_for(clock, frequency.into(), self.is_center_aligned());
+                let duty = (self.duty() as u64 * period as u64 / self.period() as u64) as u32;
+
+                self.set_period(period);
+                self.set_duty(duty);
+            }
+
+            /// Counter clocks spent at the active level each period
+            pub fn duty(&self) -> u32 {
+                self.pwm().$cdty.read().cdty().bits()
+            }
+
+            /// Sets the duty cycle in counter clocks, up to `period`.  A
+            /// running channel switches over at the start of the next period.
+            pub fn set_duty(&mut self, duty: u32) {
+                let duty = duty.min(self.period());
+
+                match self.is_enabled() {
+                    true => self.pwm().$cdtyupd.write_with_zero(|w| unsafe { w.cdtyupd().bits(duty) }),
+                    false => self.pwm().$cdty.write(|w| unsafe { w.cdty().bits(duty) }),
+                }
+            }
+
+            /// Current counter value
+            pub fn counter(&self) -> u32 {
+                self.pwm().$ccnt.read().cnt().bits()
+            }
+
+            /// Changes the dead times set up by `ChannelConfig::dead_time`.  A
+            /// running channel switches over at the start of the next period.
+            pub fn set_dead_time(&mut self, high: u16, low: u16) {
+                assert!(self.pwm().$cmr.read().dte().bit_is_set());
+
+                match self.is_enabled() {
+                    true => self.pwm().$dtupd.write_with_zero(|w| unsafe { w.dthupd().bits(high).dtlupd().bits(low) }),
+                    false => self.pwm().$dt.write(|w| unsafe { w.dth().bits(high).dtl().bits(low) }),
+                }
+            }
+
+            /// Forces an output to `level`, whatever the duty cycle
+            pub fn override_output(&mut self, output: Output, level: bool, update: Update) {
+                override_output(override_mask($index, output), level, update);
+            }
+
+            /// Hands an overridden output back to the waveform generator
+            pub fn release_output(&mut self, output: Output, update: Update) {
+                release_output(override_mask($index, output), update);
+            }
+
+            /// Whether an output is currently overridden
+            pub fn is_overridden(&self, output: Output) -> bool {
+                self.pwm().os.read().bits() & override_mask($index, output) != 0
+            }
+        }
+
+        #[cfg(feature = "unproven")]
+        impl<PINS> hal::PwmPin for Channel<$CX, PINS> {
+            type Duty = u32;
+
+            fn disable(&mut self) {
+                <Channel<$CX, PINS>>::disable(self)
+            }
+
+            fn enable(&mut self) {
+                <Channel<$CX, PINS>>::enable(self)
+            }
+
+            fn get_duty(&self) -> u32 {
+                self.duty()
+            }
+
+            fn get_max_duty(&self) -> u32 {
+                self.period()
+            }
+
+            fn set_duty(&mut self, duty: u32) {
+                <Channel<$CX, PINS>>::set_duty(self, duty)
+            }
+        }
+    };
+}
+
+channel!(C0, 0, chid0, cmr0, cdty0, cdtyupd0, cprd0, cprdupd0, ccnt0, dt0, dtupd0);
+channel!(C1, 1, chid1, cmr1, cdty1, cdtyupd1, cprd1, cprdupd1, ccnt1, dt1, dtupd1);
+channel!(C2, 2, chid2, cmr2, cdty2, cdtyupd2, cprd2, cprdupd2, ccnt2, dt2, dtupd2);
+channel!(C3, 3, chid3, cmr3, cdty3, cdtyupd3, cprd3, cprdupd3, ccnt3, dt3, dtupd3);
+channel!(C4, 4, chid4, cmr4, cdty4, cdtyupd4, cprd4, cprdupd4, ccnt4, dt4, dtupd4);
+channel!(C5, 5, chid5, cmr5, cdty5, cdtyupd5, cprd5, cprdupd5, ccnt5, dt5, dtupd5);
+channel!(C6, 6, chid6, cmr6, cdty6, cdtyupd6, cprd6, cprdupd6, ccnt6, dt6, dtupd6);
+channel!(C7, 7, chid7, cmr7, cdty7, cdtyupd7, cprd7, cprdupd7, ccnt7, dt7, dtupd7);
+
+/// Runs `$body` with `$ch` bound to the channel picked by `$id`
+macro_rules! with_channel {
+    ($channels:expr, $id:expr, |$ch:ident| $body:expr) => {
+        match $id {
+            ChannelId::C0 => { let $ch = &mut $channels.ch0; $body },
+            ChannelId::C1 => { let $ch = &mut $channels.ch1; $body },
+            ChannelId::C2 => { let $ch = &mut $channels.ch2; $body },
+            ChannelId::C3 => { let $ch = &mut $channels.ch3; $body },
+            ChannelId::C4 => { let $ch = &mut $channels.ch4; $body },
+            ChannelId::C5 => { let $ch = &mut $channels.ch5; $body },
+            ChannelId::C6 => { let $ch = &mut $channels.ch6; $body },
+            ChannelId::C7 => { let $ch = &mut $channels.ch7; $body },
+        }
+    };
+}
+
+/// Treats the channels as one multi channel output, so they should share a
+/// counter clock and alignment.  `set_period` applies to every channel, the
+/// period and maximum duty cycle are read back from channel 0.
+#[cfg(feature = "unproven")]
+impl hal::Pwm for Channels {
+    type Channel = ChannelId;
+    type Time = Hertz;
+    type Duty = u32;
+
+    fn disable(&mut self, channel: ChannelId) {
+        with_channel!(self, channel, |ch| ch.disable())
+    }
+
+    fn enable(&mut self, channel: ChannelId) {
+        with_channel!(self, channel, |ch| ch.enable())
+    }
+
+    fn get_period(&self) -> Hertz {
+        self.ch0.frequency()
+    }
+
+    fn get_duty(&self, channel: ChannelId) -> u32 {
+        match channel {
+            ChannelId::C0 => self.ch0.duty(),
+            ChannelId::C1 => self.ch1.duty(),
+            ChannelId::C2 => self.ch2.duty(),
+            ChannelId::C3 => self.ch3.duty(),
+            ChannelId::C4 => self.ch4.duty(),
+            ChannelId::C5 => self.ch5.duty(),
+            ChannelId::C6 => self.ch6.duty(),
+            ChannelId::C7 => self.ch7.duty(),
+        }
+    }
+
+    fn get_max_duty(&self) -> u32 {
+        self.ch0.period()
+    }
+
+    fn set_duty(&mut self, channel: ChannelId, duty: u32) {
+        with_channel!(self, channel, |ch| ch.set_duty(duty))
+    }
+
+    fn set_period<P: Into<Hertz>>(&mut self, period: P) {
+        let frequency = period.into();
+        self.ch0.set_frequency(frequency);
+        self.ch1.set_frequency(frequency);
+        self.ch2.set_frequency(frequency);
+        self.ch3.set_frequency(frequency);
+        self.ch4.set_frequency(frequency);
+        self.ch5.set_frequency(frequency);
+        self.ch6.set_frequency(frequency);
+        self.ch7.set_frequency(frequency);
+    }
+}
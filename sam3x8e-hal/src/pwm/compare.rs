@@ -0,0 +1,230 @@
+/*
+ *    This file (src/pwm/compare.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! PWM comparison units and event lines
+//!
+//! The eight comparison units watch channel 0's counter and match when it
+//! reaches their value, optionally only counting up or down in center aligned
+//! mode.  A match can interrupt and pulse either of the two event lines, which
+//! the ADC can use as its conversion trigger.  Sampling the current of a
+//! center aligned motor drive in the middle of the period, where the switches
+//! are quiet:
+//!
+//! ```ignore
+//! let period = channels.ch0.period();
+//! pwm.configure_comparison(CompareUnit::U0, period, &CompareConfig::new());
+//! pwm.connect_event_line(EventLine::Line0, CompareUnit::U0);
+//! ```
+
+use crate::pwm::{take_isr2, Pwm, MAX_PERIOD};
+
+/// Comparison units
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompareUnit {
+    U0,
+    U1,
+    U2,
+    U3,
+    U4,
+    U5,
+    U6,
+    U7,
+}
+
+impl CompareUnit {
+    fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+/// PWM event lines, which can trigger ADC conversions
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EventLine {
+    Line0,
+    Line1,
+}
+
+/// Counter direction a comparison matches on.  CVM
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    /// Counting up, the only direction of a left aligned counter
+    Up,
+    /// Counting down, center aligned counters only
+    Down,
+}
+
+/// Comparison unit configuration
+pub struct CompareConfig {
+    direction: Direction,
+    period: u8,
+    offset: u8,
+    update_period: u8,
+}
+
+impl Default for CompareConfig {
+    fn default() -> CompareConfig {
+        CompareConfig {
+            direction: Direction::Up,
+            period: 1,
+            offset: 0,
+            update_period: 1,
+        }
+    }
+}
+
+impl CompareConfig {
+    pub fn new() -> Self {
+        CompareConfig {..Self::default()}
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// CPR: compares once every `period` PWM periods, 1 to 16
+    pub fn period(mut self, period: u8) -> Self {
+        assert!((1..=16).contains(&period));
+        self.period = period;
+        self
+    }
+
+    /// CTR: which PWM period within `period` the comparison happens in
+    pub fn offset(mut self, offset: u8) -> Self {
+        assert!(offset < 16);
+        self.offset = offset;
+        self
+    }
+
+    /// CUPR: values set on a running unit take effect once every
+    /// `update_period` comparison periods, 1 to 16
+    pub fn update_period(mut self, update_period: u8) -> Self {
+        assert!((1..=16).contains(&update_period));
+        self.update_period = update_period;
+        self
+    }
+}
+
+macro_rules! with_unit {
+    ($pwm:expr, $unit:expr, |$cmpv:ident, $cmpvupd:ident, $cmpm:ident, $cmpmupd:ident| $body:expr) => {
+        match $unit {
+            CompareUnit::U0 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv0, &$pwm.cmpvupd0, &$pwm.cmpm0, &$pwm.cmpmupd0); $body },
+            CompareUnit::U1 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv1, &$pwm.cmpvupd1, &$pwm.cmpm1, &$pwm.cmpmupd1); $body },
+            CompareUnit::U2 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv2, &$pwm.cmpvupd2, &$pwm.cmpm2, &$pwm.cmpmupd2); $body },
+            CompareUnit::U3 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv3, &$pwm.cmpvupd3, &$pwm.cmpm3, &$pwm.cmpmupd3); $body },
+            CompareUnit::U4 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv4, &$pwm.cmpvupd4, &$pwm.cmpm4, &$pwm.cmpmupd4); $body },
+            CompareUnit::U5 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv5, &$pwm.cmpvupd5, &$pwm.cmpm5, &$pwm.cmpmupd5); $body },
+            CompareUnit::U6 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv6, &$pwm.cmpvupd6, &$pwm.cmpm6, &$pwm.cmpmupd6); $body },
+            CompareUnit::U7 => { let ($cmpv, $cmpvupd, $cmpm, $cmpmupd) = (&$pwm.cmpv7, &$pwm.cmpvupd7, &$pwm.cmpm7, &$pwm.cmpmupd7); $body },
+        }
+    };
+}
+
+/// CMPMx bits in IER2 / IDR2 / ISR2
+const MATCH_SHIFT: u32 = 8;
+/// CMPUx bits in IER2 / IDR2 / ISR2
+const UPDATE_SHIFT: u32 = 16;
+
+impl Pwm {
+    /// Sets up and enables a comparison unit.  While channel 0 is running
+    /// the change goes through the update registers and takes effect at the
+    /// unit's next update.
+    pub fn configure_comparison(&mut self, unit: CompareUnit, value: u32, config: &CompareConfig) {
+        assert!(value <= MAX_PERIOD);
+        let down = config.direction == Direction::Down;
+        let running = self.pwm.sr.read().chid0().bit_is_set();
+
+        with_unit!(self.pwm, unit, |cmpv, cmpvupd, cmpm, cmpmupd| {
+            match running {
+                true => {
+                    cmpvupd.write_with_zero(|w| unsafe { w.cvupd().bits(value).cvmupd().bit(down) });
+                    cmpmupd.write_with_zero(|w| unsafe {
+                        w.cenupd().set_bit()
+                            .ctrupd().bits(config.offset)
+                            .cprupd().bits(config.period - 1)
+                            .cuprupd().bits(config.update_period - 1)
+                    });
+                }
+                false => {
+                    cmpv.write(|w| unsafe { w.cv().bits(value).cvm().bit(down) });
+                    cmpm.write(|w| unsafe {
+                        w.cen().set_bit()
+                            .ctr().bits(config.offset)
+                            .cpr().bits(config.period - 1)
+                            .cupr().bits(config.update_period - 1)
+                    });
+                }
+            }
+        });
+    }
+
+    /// Moves a comparison, keeping its direction.  Glitch free like
+    /// `configure_comparison`.
+    pub fn set_comparison_value(&mut self, unit: CompareUnit, value: u32) {
+        assert!(value <= MAX_PERIOD);
+        let running = self.pwm.sr.read().chid0().bit_is_set();
+
+        with_unit!(self.pwm, unit, |cmpv, cmpvupd, _cmpm, _cmpmupd| {
+            let down = cmpv.read().cvm().bit_is_set();
+            match running {
+                true => cmpvupd.write_with_zero(|w| unsafe { w.cvupd().bits(value).cvmupd().bit(down) }),
+                false => cmpv.modify(|_, w| unsafe { w.cv().bits(value) }),
+            }
+        });
+    }
+
+    pub fn comparison_value(&self, unit: CompareUnit) -> u32 {
+        with_unit!(self.pwm, unit, |cmpv, _cmpvupd, _cmpm, _cmpmupd| cmpv.read().cv().bits())
+    }
+
+    /// Stops a comparison unit matching, immediately
+    pub fn disable_comparison(&mut self, unit: CompareUnit) {
+        with_unit!(self.pwm, unit, |_cmpv, _cmpvupd, cmpm, _cmpmupd| {
+            cmpm.modify(|_, w| w.cen().clear_bit())
+        });
+    }
+
+    /// Pulses `line` whenever `unit` matches.  Several units can share a line.
+    pub fn connect_event_line(&mut self, line: EventLine, unit: CompareUnit) {
+        let mask = 1 << unit.index();
+        self.pwm.elmr[line as usize].modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+    }
+
+    pub fn disconnect_event_line(&mut self, line: EventLine, unit: CompareUnit) {
+        let mask = 1 << unit.index();
+        self.pwm.elmr[line as usize].modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+    }
+
+    /// Starts interrupting when `unit` matches
+    pub fn listen_comparison(&mut self, unit: CompareUnit) {
+        self.pwm.ier2.write_with_zero(|w| unsafe { w.bits(1 << (MATCH_SHIFT + unit.index() as u32)) });
+    }
+
+    pub fn unlisten_comparison(&mut self, unit: CompareUnit) {
+        self.pwm.idr2.write_with_zero(|w| unsafe { w.bits(1 << (MATCH_SHIFT + unit.index() as u32)) });
+    }
+
+    /// Whether `unit` matched since the last call
+    pub fn take_comparison_match(&mut self, unit: CompareUnit) -> bool {
+        take_isr2(1 << (MATCH_SHIFT + unit.index() as u32)) != 0
+    }
+
+    /// Whether `unit` applied its update registers since the last call
+    pub fn take_comparison_update(&mut self, unit: CompareUnit) -> bool {
+        take_isr2(1 << (UPDATE_SHIFT + unit.index() as u32)) != 0
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ *    This file (src/pwm/fault.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! PWM fault protection
+//!
+//! While a fault is active the outputs of every channel it's mapped to are
+//! forced to their fault protection values by the hardware, without any
+//! software involved.  Faults come from the PWMFI pins, the main oscillator
+//! failure detector, the ADC comparison and the timer counters.
+//!
+//! ```ignore
+//! pwm.configure_fault(FaultInput::Pwmfi0, &FaultConfig::new().active_high(false));
+//! pwm.protect(ChannelId::C0, FaultInput::Pwmfi0 | FaultInput::Adc, false, false);
+//! pwm.listen_faults(FaultInput::Pwmfi0.into());
+//! ```
+
+use core::ops::BitOr;
+
+use crate::pwm::{take_isr1, ChannelId, Pwm};
+
+/// Fault inputs.  The PWMFI pins need to be switched to peripheral B.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FaultInput {
+    /// PWMFI0 on PA5
+    Pwmfi0,
+    /// PWMFI1 on PA3
+    Pwmfi1,
+    /// PWMFI2 on PD6
+    Pwmfi2,
+    /// Main crystal oscillator failure, from the PMC
+    MainOscillator,
+    /// ADC comparison match
+    Adc,
+    /// Timer counter 0
+    Timer0,
+    /// Timer counter 1
+    Timer1,
+}
+
+impl FaultInput {
+    fn mask(self) -> u8 {
+        let index = match self {
+            FaultInput::Pwmfi0 => 0,
+            FaultInput::Pwmfi1 => 1,
+            FaultInput::Pwmfi2 => 2,
+            FaultInput::MainOscillator => 3,
+            FaultInput::Adc => 4,
+            FaultInput::Timer0 => 5,
+            FaultInput::Timer1 => 6,
+        };
+
+        1 << index
+    }
+}
+
+/// A set of fault inputs
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Faults(u8);
+
+impl Faults {
+    pub fn empty() -> Self {
+        Faults(0)
+    }
+
+    pub fn contains(self, input: FaultInput) -> bool {
+        self.0 & input.mask() != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<FaultInput> for Faults {
+    fn from(input: FaultInput) -> Self {
+        Faults(input.mask())
+    }
+}
+
+impl BitOr for FaultInput {
+    type Output = Faults;
+
+    fn bitor(self, other: FaultInput) -> Faults {
+        Faults(self.mask() | other.mask())
+    }
+}
+
+impl BitOr<FaultInput> for Faults {
+    type Output = Faults;
+
+    fn bitor(self, other: FaultInput) -> Faults {
+        Faults(self.0 | other.mask())
+    }
+}
+
+/// How an active fault goes away
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FaultClear {
+    /// FMOD = 1: the fault stays active until the input is back to its
+    /// inactive level and the fault has been cleared with `clear_faults`
+    Manual,
+    /// FMOD = 0: the fault clears itself once the input is back to its
+    /// inactive level
+    Automatic,
+}
+
+/// Fault input configuration
+pub struct FaultConfig {
+    active_high: bool,
+    clear: FaultClear,
+    filter: bool,
+}
+
+impl Default for FaultConfig {
+    fn default() -> FaultConfig {
+        FaultConfig {
+            active_high: true,
+            clear: FaultClear::Manual,
+            filter: false,
+        }
+    }
+}
+
+impl FaultConfig {
+    pub fn new() -> Self {
+        FaultConfig {..Self::default()}
+    }
+
+    /// FPOL: level of the input that signals a fault
+    pub fn active_high(mut self, active_high: bool) -> Self {
+        self.active_high = active_high;
+        self
+    }
+
+    /// FMOD
+    pub fn clear(mut self, clear: FaultClear) -> Self {
+        self.clear = clear;
+        self
+    }
+
+    /// FFIL: ignores pulses shorter than a few master clock periods
+    pub fn filter(mut self, filter: bool) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// FCHIDx bits in IER1 / IDR1 / ISR1
+const FAULT_EVENT_SHIFT: u32 = 16;
+
+fn set_bits(bits: u32, mask: u32, set: bool) -> u32 {
+    match set {
+        true => bits | mask,
+        false => bits & !mask,
+    }
+}
+
+impl Pwm {
+    /// Sets the polarity, clearing and filtering of a fault input
+    pub fn configure_fault(&mut self, input: FaultInput, config: &FaultConfig) {
+        let mask = input.mask() as u32;
+
+        self.pwm.fmr.modify(|r, w| unsafe {
+            let bits = set_bits(r.bits(), mask, config.active_high);
+            let bits = set_bits(bits, mask << 8, config.clear == FaultClear::Manual);
+            w.bits(set_bits(bits, mask << 16, config.filter))
+        });
+    }
+
+    /// Maps `faults` to a channel.  While any of them is active PWMH is
+    /// forced to `high` and PWML to `low`.  An empty set unprotects the
+    /// channel.
+    pub fn protect(&mut self, channel: ChannelId, faults: Faults, high: bool, low: bool) {
+        let index = channel.index();
+
+        self.pwm.fpv.modify(|r, w| unsafe {
+            let bits = set_bits(r.bits(), 1 << index, high);
+            w.bits(set_bits(bits, 1 << (index + 16), low))
+        });
+
+        // FPE1 holds channels 0 to 3, FPE2 channels 4 to 7, a byte each
+        let shift = (index % 4) * 8;
+        let field = |bits: u32| (bits & !(0xff << shift)) | ((faults.0 as u32) << shift);
+        match index < 4 {
+            true => self.pwm.fpe1.modify(|r, w| unsafe { w.bits(field(r.bits())) }),
+            false => self.pwm.fpe2.modify(|r, w| unsafe { w.bits(field(r.bits())) }),
+        }
+    }
+
+    /// Faults currently active, forcing their channels' outputs
+    pub fn active_faults(&self) -> Faults {
+        Faults(self.pwm.fsr.read().fs().bits())
+    }
+
+    /// Fault inputs currently at their fault level
+    pub fn fault_inputs(&self) -> Faults {
+        let fsr = self.pwm.fsr.read();
+        let fmr = self.pwm.fmr.read();
+
+        // FIV holds raw levels, FPOL says which level is the fault
+        Faults(!(fsr.fiv().bits() ^ fmr.fpol().bits()) & 0x7f)
+    }
+
+    /// Clears `FaultClear::Manual` faults.  A fault whose input is still at
+    /// its fault level stays active, `FaultClear::Automatic` faults need no
+    /// clearing.
+    pub fn clear_faults(&mut self, faults: Faults) {
+        self.pwm.fcr.write_with_zero(|w| unsafe { w.fclr().bits(faults.0) });
+    }
+
+    /// Starts interrupting when one of `faults` trips
+    pub fn listen_faults(&mut self, faults: Faults) {
+        self.pwm.ier1.write_with_zero(|w| unsafe { w.bits((faults.0 as u32) << FAULT_EVENT_SHIFT) });
+    }
+
+    pub fn unlisten_faults(&mut self, faults: Faults) {
+        self.pwm.idr1.write_with_zero(|w| unsafe { w.bits((faults.0 as u32) << FAULT_EVENT_SHIFT) });
+    }
+
+    /// Faults that tripped since the last call.  The channel counter events
+    /// read along with them are kept for `Channel::take_period_end`.
+    pub fn take_fault_events(&mut self) -> Faults {
+        Faults((take_isr1(0xff << FAULT_EVENT_SHIFT) >> FAULT_EVENT_SHIFT) as u8)
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ *    This file (src/pwm/sync.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! PWM synchronous channels
+//!
+//! Synchronous channels run off channel 0's counter, so they share its clock,
+//! alignment and period and start and stop with it.  Their new duty cycles,
+//! periods and dead times are applied together at an update, once every
+//! `update_period` periods:
+//!
+//! * `Manual`: when software unlocks the update with `unlock_update`
+//! * `Automatic`: duty cycles on their own, periods and dead times still need
+//!   `unlock_update`
+//! * `Pdc`: like `Automatic`, but the PDC writes the duty cycles.  Each update
+//!   takes one duty cycle per synchronous channel, lowest channel first, so a
+//!   buffer holds the updates back to back.
+//!
+//! ```ignore
+//! let sync = channels.ch0.synchronize(&[ChannelId::C1, ChannelId::C2], UpdateMode::Pdc, 1);
+//! let transfer = sync.write_all(&WAVEFORM);
+//! ```
+
+use crate::pac::{pwm, PWM};
+use crate::pdc;
+use crate::pwm::{take_isr2, Channel, ChannelId, C0};
+
+/// How synchronous channel updates are triggered.  UPDM
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpdateMode {
+    Manual,
+    Automatic,
+    Pdc,
+}
+
+/// Synchronous channel interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// WRDY: new duty cycles can be written for the next update
+    WriteReady,
+    /// ENDTX: the PDC's current buffer is done
+    EndOfTransfer,
+    /// TXBUFE: both PDC buffers are done
+    BufferEmpty,
+    /// UNRE: an update came around before the duty cycles were written
+    Underrun,
+}
+
+impl Event {
+    /// IER2 / IDR2 / ISR2 bit
+    fn mask(self) -> u32 {
+        match self {
+            Event::WriteReady => 1 << 0,
+            Event::EndOfTransfer => 1 << 1,
+            Event::BufferEmpty => 1 << 2,
+            Event::Underrun => 1 << 3,
+        }
+    }
+}
+
+/// Channel 0 along with the channels synchronised to it
+pub struct Synchronous<PINS> {
+    channel: Channel<C0, PINS>,
+}
+
+impl<PINS> Channel<C0, PINS> {
+    /// Makes `channels` synchronous with channel 0.  All of them have to be
+    /// disabled.  `update_period` is the number of periods between updates,
+    /// 1 to 16.
+    pub fn synchronize(mut self, channels: &[ChannelId], mode: UpdateMode, update_period: u8) -> Synchronous<PINS> {
+        assert!((1..=16).contains(&update_period));
+        self.disable();
+
+        let sync = channels.iter().fold(1, |sync, channel| sync | 1 << channel.index());
+        let pwm = unsafe { &*PWM::ptr() };
+
+        pwm.scm.write(|w| {
+            let w = unsafe { w.bits(sync) };
+            match mode {
+                UpdateMode::Manual => w.updm().mode0(),
+                UpdateMode::Automatic => w.updm().mode1(),
+                UpdateMode::Pdc => w.updm().mode2(),
+            }
+        });
+        pwm.scup.write(|w| unsafe { w.upr().bits(update_period - 1) });
+
+        Synchronous { channel: self }
+    }
+}
+
+impl<PINS> Synchronous<PINS> {
+    fn pwm(&self) -> &pwm::RegisterBlock {
+        unsafe { &*PWM::ptr() }
+    }
+
+    /// Channel 0, which sets the period of every synchronous channel
+    pub fn channel(&mut self) -> &mut Channel<C0, PINS> {
+        &mut self.channel
+    }
+
+    /// Starts every synchronous channel
+    pub fn enable(&mut self) {
+        self.channel.enable();
+    }
+
+    /// Stops every synchronous channel
+    pub fn disable(&mut self) {
+        self.channel.disable();
+    }
+
+    /// Applies the values written to the update registers at the next update
+    pub fn unlock_update(&mut self) {
+        self.pwm().scuc.write(|w| w.updulock().set_bit());
+    }
+
+    /// Whether an unlocked update is still waiting to be applied
+    pub fn is_update_pending(&self) -> bool {
+        self.pwm().scuc.read().updulock().bit_is_set()
+    }
+
+    /// Changes the number of periods between updates, 1 to 16
+    pub fn set_update_period(&mut self, update_period: u8) {
+        assert!((1..=16).contains(&update_period));
+        self.pwm().scupupd.write(|w| unsafe { w.uprupd().bits(update_period - 1) });
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.pwm().ier2.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.pwm().idr2.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Whether `event` happened since the last call
+    pub fn take_event(&mut self, event: Event) -> bool {
+        take_isr2(event.mask()) != 0
+    }
+
+    /// Stops the channels and makes them independent again
+    pub fn release(mut self) -> Channel<C0, PINS> {
+        self.disable();
+        self.pwm().scm.write(|w| unsafe { w.bits(0) });
+        self.channel
+    }
+}
+
+impl<PINS> pdc::Transmit for Synchronous<PINS> {
+    type Word = u16;
+    type Channel = pwm::RegisterBlock;
+
+    fn tx_channel(&self) -> &pwm::RegisterBlock {
+        self.pwm()
+    }
+}
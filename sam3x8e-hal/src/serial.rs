@@ -0,0 +1,131 @@
+/*
+ *    This file (src/serial.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Serial communication over the UART and USART peripherals
+
+use crate::gpio::{PeripheralA, PeripheralB};
+use crate::gpio::pioa::{PA8, PA9, PA10, PA11, PA12, PA13, PA16, PA17};
+use crate::gpio::piob::{PB20, PB21, PB24};
+use crate::gpio::piod::{PD4, PD5};
+use crate::pac::{Interrupt, UART, USART0, USART1, USART2, USART3};
+use crate::pmc::Clocks;
+use crate::time::Bps;
+
+pub mod buffered;
+pub mod irda;
+pub mod iso7816;
+pub mod lin;
+pub mod manchester;
+pub mod uart;
+pub mod usart;
+
+/// Serial errors
+#[derive(Debug)]
+pub enum Error {
+    /// A character was received before the previous one was read
+    Overrun,
+    /// A stop bit was read as zero
+    Framing,
+    /// The parity of a received character was wrong
+    Parity,
+    /// The Manchester decoder found a missing transition
+    Manchester,
+    /// ISO7816: a character was repeated the maximum number of times
+    Iteration,
+    /// ISO7816: the card answered with a NACK
+    Nack,
+}
+
+/// Interrupt events
+pub enum Event {
+    /// A character has been received
+    RxReady,
+    /// The transmit holding register is empty
+    TxReady,
+    /// The transmit holding and shift registers are both empty
+    TxEmpty,
+}
+
+/// Parity bit
+#[derive(Clone, Copy, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+    /// Always zero
+    Space,
+    /// Always one
+    Mark,
+}
+
+/// Interrupt control shared by the UART and USART drivers
+pub trait Listen {
+    /// The peripheral's line on the NVIC
+    const INTERRUPT: Interrupt;
+
+    /// Starts listening for an interrupt event
+    fn listen(&mut self, event: Event);
+
+    /// Stops listening for an interrupt event
+    fn unlisten(&mut self, event: Event);
+}
+
+/// Pins that can carry the transmit line (TXD) of a UART or USART
+pub trait TxPin<USART> {}
+
+/// Pins that can carry the receive line (RXD) of a UART or USART
+pub trait RxPin<USART> {}
+
+/// Pins that can carry the serial clock (SCK) of a USART
+pub trait SckPin<USART> {}
+
+// UART pin multiplexing.  Datasheet §34.4.1
+impl TxPin<UART> for PA9<PeripheralA> {}
+impl RxPin<UART> for PA8<PeripheralA> {}
+
+// USART pin multiplexing.  Datasheet §35.5.1
+impl TxPin<USART0> for PA11<PeripheralA> {}
+impl RxPin<USART0> for PA10<PeripheralA> {}
+impl SckPin<USART0> for PA17<PeripheralB> {}
+
+impl TxPin<USART1> for PA13<PeripheralA> {}
+impl RxPin<USART1> for PA12<PeripheralA> {}
+impl SckPin<USART1> for PA16<PeripheralA> {}
+
+impl TxPin<USART2> for PB20<PeripheralA> {}
+impl RxPin<USART2> for PB21<PeripheralA> {}
+impl SckPin<USART2> for PB24<PeripheralA> {}
+
+// SCK3 is on PE16 which isn't bonded out on the 100 pin packages
+impl TxPin<USART3> for PD4<PeripheralB> {}
+impl RxPin<USART3> for PD5<PeripheralB> {}
+
+/// Computes the clock divider (CD) and fractional part (FP) of the baud rate
+/// generator for asynchronous operation with 16x oversampling.  Datasheet §35.7.1.1
+///
+/// Baud rate = MCK / (16 * (CD + FP / 8))
+pub(crate) fn baud_divider(clocks: &Clocks, baud_rate: Bps) -> (u16, u8) {
+    // Work in eighths so that the remainder lands in FP
+    let eighths = (clocks.master_clk().0 + baud_rate.0) / (2 * baud_rate.0);
+    let cd = eighths / 8;
+    let fp = eighths % 8;
+
+    // CD is only 16 bits wide and a value of zero disables the clock
+    assert!(cd > 0 && cd < (1 << 16));
+
+    (cd as u16, fp as u8)
+}
@@ -0,0 +1,364 @@
+/*
+ *    This file (src/serial/buffered.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Interrupt driven serial I/O through ring buffers
+//!
+//! `buffered` splits a UART or USART driver in three: a `Reader` and a
+//! `Writer` for the main loop and a `Handler` that is moved into the
+//! peripheral's interrupt handler.  They only communicate through two single
+//! producer / single consumer ring buffers, so neither side needs a critical
+//! section.
+//!
+//! ```ignore
+//! static RX: RingBuffer<256> = RingBuffer::new();
+//! static TX: RingBuffer<256> = RingBuffer::new();
+//! static HANDLER: Mutex<RefCell<Option<Handler<Usart<USART0, Pins, Normal>, 256, 256>>>> =
+//!     Mutex::new(RefCell::new(None));
+//!
+//! let (mut reader, mut writer, handler) = buffered(usart, &RX, &TX);
+//! interrupt::free(|cs| HANDLER.borrow(cs).replace(Some(handler)));
+//!
+//! #[interrupt]
+//! fn USART0() {
+//!     interrupt::free(|cs| {
+//!         if let Some(handler) = HANDLER.borrow(cs).borrow_mut().as_mut() {
+//!             handler.on_interrupt();
+//!         }
+//!     });
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use cortex_m::peripheral::NVIC;
+
+use crate::hal::serial;
+use crate::pac::Interrupt;
+use crate::serial::{Error, Event, Listen};
+
+/// A single producer / single consumer byte queue holding up to `N - 1` bytes
+pub struct RingBuffer<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    /// Index of the next byte to write, only moved by the producer
+    head: AtomicUsize,
+    /// Index of the next byte to read, only moved by the consumer
+    tail: AtomicUsize,
+    /// Error seen by the producer and the `head` it was seen at, reported to
+    /// the consumer once it has read the bytes before it
+    error: AtomicUsize,
+    split: AtomicBool,
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The producer and consumer halves never touch the same slot at the same time
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+const NO_ERROR: usize = 0;
+
+/// `error` holds the error code in its low bits and its position above them
+const ERROR_SHIFT: u32 = 3;
+const ERROR_MASK: usize = (1 << ERROR_SHIFT) - 1;
+
+fn encode_error(error: Error) -> u8 {
+    match error {
+        Error::Overrun => 1,
+        Error::Framing => 2,
+        Error::Parity => 3,
+        Error::Manchester => 4,
+        Error::Iteration => 5,
+        Error::Nack => 6,
+    }
+}
+
+fn decode_error(error: u8) -> Option<Error> {
+    match error {
+        1 => Some(Error::Overrun),
+        2 => Some(Error::Framing),
+        3 => Some(Error::Parity),
+        4 => Some(Error::Manchester),
+        5 => Some(Error::Iteration),
+        6 => Some(Error::Nack),
+        _ => None,
+    }
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            buffer: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            error: AtomicUsize::new(NO_ERROR),
+            split: AtomicBool::new(false),
+        }
+    }
+
+    /// Splits the queue into its producer and consumer halves.  Panics if the
+    /// queue has already been split.
+    pub fn split(&'static self) -> (Producer<N>, Consumer<N>) {
+        assert!(N > 1);
+        assert!(!self.split.swap(true, Ordering::AcqRel));
+
+        (Producer { ring: self }, Consumer { ring: self })
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (head + N - tail) % N
+    }
+}
+
+/// Writing end of a `RingBuffer`
+pub struct Producer<const N: usize> {
+    ring: &'static RingBuffer<N>,
+}
+
+impl<const N: usize> Producer<N> {
+    /// Appends a byte, handing it back if the queue is full
+    pub fn push(&mut self, byte: u8) -> Result<(), u8> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let next = (head + 1) % N;
+
+        if next == self.ring.tail.load(Ordering::Acquire) {
+            return Err(byte);
+        }
+
+        unsafe { (*self.ring.buffer.get())[head] = byte };
+        self.ring.head.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Records an error for the consumer to pick up after the bytes already
+    /// pushed, replacing any error it hasn't reached yet
+    fn set_error(&mut self, error: Error) {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let error = (head << ERROR_SHIFT) | encode_error(error) as usize;
+        self.ring.error.store(error, Ordering::Release);
+    }
+
+    /// Number of bytes that can be pushed before the queue is full
+    pub fn free(&self) -> usize {
+        N - 1 - self.ring.len()
+    }
+}
+
+/// Reading end of a `RingBuffer`
+pub struct Consumer<const N: usize> {
+    ring: &'static RingBuffer<N>,
+}
+
+impl<const N: usize> Consumer<N> {
+    /// Removes the oldest byte
+    pub fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        self.ring.tail.store((tail + 1) % N, Ordering::Release);
+        Some(byte)
+    }
+
+    /// Returns the oldest byte without removing it
+    pub fn peek(&self) -> Option<u8> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+
+        if tail == self.ring.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        Some(unsafe { (*self.ring.buffer.get())[tail] })
+    }
+
+    /// Returns and clears the error recorded by the producer once every byte
+    /// pushed before it has been removed
+    fn take_error(&mut self) -> Option<Error> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let mut error = self.ring.error.load(Ordering::Acquire);
+
+        loop {
+            if error == NO_ERROR || error >> ERROR_SHIFT != tail {
+                return None;
+            }
+
+            // The producer may replace the error while it's being taken
+            match self.ring.error.compare_exchange(error, NO_ERROR, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return decode_error((error & ERROR_MASK) as u8),
+                Err(current) => error = current,
+            }
+        }
+    }
+
+    /// Number of bytes waiting in the queue
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Main loop side of the receive buffer
+pub struct Reader<const RX: usize> {
+    rx: Consumer<RX>,
+}
+
+/// Main loop side of the transmit buffer
+pub struct Writer<const TX: usize> {
+    tx: Producer<TX>,
+    interrupt: Interrupt,
+}
+
+/// Interrupt side of both buffers, owns the serial driver
+pub struct Handler<S, const RX: usize, const TX: usize> {
+    serial: S,
+    rx: Producer<RX>,
+    tx: Consumer<TX>,
+}
+
+/// Splits a serial driver into ring buffered halves.  Receive interrupts are
+/// enabled straight away, the peripheral's interrupt still has to be unmasked
+/// in the NVIC.
+pub fn buffered<S, const RX: usize, const TX: usize>(
+    mut serial: S,
+    rx: &'static RingBuffer<RX>,
+    tx: &'static RingBuffer<TX>,
+) -> (Reader<RX>, Writer<TX>, Handler<S, RX, TX>)
+where
+    S: serial::Read<u8, Error = Error> + serial::Write<u8, Error = Error> + Listen,
+{
+    let (rx_producer, rx_consumer) = rx.split();
+    let (tx_producer, tx_consumer) = tx.split();
+
+    serial.listen(Event::RxReady);
+
+    (
+        Reader { rx: rx_consumer },
+        Writer { tx: tx_producer, interrupt: S::INTERRUPT },
+        Handler { serial, rx: rx_producer, tx: tx_consumer },
+    )
+}
+
+impl<S, const RX: usize, const TX: usize> Handler<S, RX, TX>
+where
+    S: serial::Read<u8, Error = Error> + serial::Write<u8, Error = Error> + Listen,
+{
+    /// Moves data between the peripheral and the ring buffers.  Call this from
+    /// the UART / USARTn interrupt handler.
+    pub fn on_interrupt(&mut self) {
+        // Drain the receiver
+        loop {
+            match self.serial.read() {
+                Ok(byte) => {
+                    if self.rx.push(byte).is_err() {
+                        // The main loop isn't keeping up
+                        self.rx.set_error(Error::Overrun);
+                    }
+                },
+                Err(nb::Error::Other(error)) => self.rx.set_error(error),
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+
+        // Refill the transmitter
+        while let Some(byte) = self.tx.peek() {
+            match self.serial.write(byte) {
+                Ok(()) => {
+                    self.tx.pop();
+                },
+                Err(nb::Error::Other(_)) => {
+                    // Nothing sensible to do from here, drop the byte
+                    self.tx.pop();
+                },
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+
+        // TXRDY stays set while the holding register is empty, only listen for
+        // it while there's something to send
+        match self.tx.is_empty() {
+            true => self.serial.unlisten(Event::TxReady),
+            false => self.serial.listen(Event::TxReady),
+        }
+    }
+
+    /// Releases the serial driver.  The ring buffers stay split.
+    pub fn free(mut self) -> S {
+        self.serial.unlisten(Event::RxReady);
+        self.serial.unlisten(Event::TxReady);
+        self.serial
+    }
+}
+
+impl<const RX: usize> Reader<RX> {
+    /// Number of received bytes waiting to be read
+    pub fn len(&self) -> usize {
+        self.rx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rx.is_empty()
+    }
+}
+
+impl<const RX: usize> serial::Read<u8> for Reader<RX> {
+    type Error = Error;
+
+    /// Errors are reported once, after the bytes received before them and
+    /// ahead of the bytes received after them
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        if let Some(error) = self.rx.take_error() {
+            return Err(nb::Error::Other(error));
+        }
+
+        self.rx.pop().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl<const TX: usize> Writer<TX> {
+    /// Number of bytes that can be queued without blocking
+    pub fn free(&self) -> usize {
+        self.tx.free()
+    }
+}
+
+impl<const TX: usize> serial::Write<u8> for Writer<TX> {
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+        let result = self.tx.push(byte).map_err(|_| nb::Error::WouldBlock);
+
+        // Run the handler so it starts listening for TXRDY
+        NVIC::pend(self.interrupt);
+
+        result
+    }
+
+    /// Returns once the transmit buffer has been handed to the peripheral.  The
+    /// last character may still be shifting out.
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        match self.tx.free() == TX - 1 {
+            true => Ok(()),
+            false => Err(nb::Error::WouldBlock),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ *    This file (src/serial/irda.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! IrDA SIR mode.  Datasheet §35.7.5
+//!
+//! The USART modulates and demodulates the 3/16 bit period pulses used by
+//! IrDA transceivers.  The link is half duplex: the receiver would see the
+//! transceiver's own transmissions, so only one direction is enabled at a time.
+
+use core::marker::PhantomData;
+
+use crate::pac::{USART0, USART1, USART2, USART3};
+use crate::pmc::Pmc;
+use crate::serial::{baud_divider, usart::Usart, RxPin, TxPin};
+use crate::time::{Bps, U32Ext};
+
+/// IrDA mode
+pub struct IrDA;
+
+/// IrDA configuration
+pub struct Config {
+    baud_rate: Bps,
+    filter: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baud_rate: 9_600.bps(),
+            filter: 0,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// 2.4 to 115.2 kbps
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// IRDA_FILTER: pulses shorter than this many master clock periods are
+    /// rejected by the demodulator
+    pub fn filter(mut self, filter: u8) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+pub trait IrDAExt<PINS>: Sized {
+    /// Configures the USART in IrDA mode, starting out in receive
+    fn irda(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Usart<Self, PINS, IrDA>;
+}
+
+macro_rules! irda {
+    ($USARTX:ident) => {
+        impl<TX, RX> IrDAExt<(TX, RX)> for $USARTX
+        where
+            TX: TxPin<$USARTX>,
+            RX: RxPin<$USARTX>,
+        {
+            fn irda(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Usart<Self, (TX, RX), IrDA> {
+                Usart::<$USARTX, (TX, RX), IrDA>::reset(&self, pmc);
+
+                self.mr().write_with_zero(|w|
+                    w
+                    .usart_mode().irda()
+                    .usclks().mck()
+                    .chrl()._8_bit()
+                    .par().no()
+                    .nbstop()._1_bit()
+                    .over().clear_bit()
+                );
+
+                let (cd, fp) = baud_divider(&pmc.clocks, config.baud_rate);
+                self.brgr.write(|w| unsafe { w.cd().bits(cd).fp().bits(fp) });
+
+                self.if_.write(|w| unsafe { w.irda_filter().bits(config.filter) });
+
+                let mut usart = Usart { usart: self, pins, _mode: PhantomData::<IrDA> };
+                usart.receive();
+                usart
+            }
+        }
+
+        impl<PINS> Usart<$USARTX, PINS, IrDA> {
+            /// Turns the link around to receive
+            pub fn receive(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.txdis().set_bit().rxen().set_bit());
+            }
+
+            /// Turns the link around to transmit.  Flush the transmitter before
+            /// switching back to receive.
+            pub fn transmit(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.rxdis().set_bit().txen().set_bit());
+            }
+        }
+    };
+}
+
+irda!(USART0);
+irda!(USART1);
+irda!(USART2);
+irda!(USART3);
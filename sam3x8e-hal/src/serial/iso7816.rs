@@ -0,0 +1,243 @@
+/*
+ *    This file (src/serial/iso7816.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! ISO7816 smart card interface, protocols T = 0 and T = 1.  Datasheet §35.7.4
+//!
+//! The card's I/O line is connected to TXD and its CLK input to SCK, which the
+//! USART drives continuously.  The card reset line is an ordinary GPIO.
+//!
+//! Characters are always 8 data bits with even parity.  In T = 0 a character
+//! with a parity error is NACKed by the receiver and repeated by the
+//! transmitter, up to `max_iterations` times.
+//!
+//! The I/O line is half duplex and enabling the receiver and transmitter
+//! together gives unpredictable results.  The USART starts out receiving, for
+//! the answer to reset, and `transmit` and `receive` turn the link around.
+
+use core::marker::PhantomData;
+
+use crate::pac::{USART0, USART1, USART2};
+use crate::pmc::Pmc;
+use crate::serial::{usart::Usart, SckPin, TxPin};
+use crate::time::{Hertz, U32Ext};
+
+/// ISO7816 mode
+pub struct Iso7816;
+
+/// Transmission protocol
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// Character oriented, errors are signalled and repeated
+    T0,
+    /// Block oriented, errors are handled by the block protocol
+    T1,
+}
+
+/// Bit order and level convention, as announced by the card's TS character
+#[derive(Clone, Copy, PartialEq)]
+pub enum Convention {
+    /// LSB first, high level is a one
+    Direct,
+    /// MSB first, low level is a one
+    Inverse,
+}
+
+/// ISO7816 configuration
+pub struct Config {
+    protocol: Protocol,
+    convention: Convention,
+    clock: Hertz,
+    fi_di_ratio: u16,
+    guard_time: u8,
+    max_iterations: u8,
+    inhibit_nack: bool,
+    disable_successive_nack: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            protocol: Protocol::T0,
+            convention: Convention::Direct,
+            clock: 3_571_200.hz(),
+            // Fi = 372, Di = 1, the default until the card says otherwise
+            fi_di_ratio: 372,
+            guard_time: 0,
+            max_iterations: 3,
+            inhibit_nack: false,
+            disable_successive_nack: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn convention(mut self, convention: Convention) -> Self {
+        self.convention = convention;
+        self
+    }
+
+    /// Frequency of the clock supplied to the card on SCK (1 to 5 MHz).  This
+    /// is rounded down to MCK divided by an integer.
+    pub fn clock<T>(mut self, clock: T) -> Self
+    where
+        T: Into<Hertz>,
+    {
+        self.clock = clock.into();
+        self
+    }
+
+    /// FI_DI_RATIO: number of card clock periods per bit (ETU), 11 bits
+    pub fn fi_di_ratio(mut self, ratio: u16) -> Self {
+        assert!(ratio > 0 && ratio < (1 << 11));
+        self.fi_di_ratio = ratio;
+        self
+    }
+
+    /// TG: extra guard time in bit periods inserted between transmitted characters
+    pub fn guard_time(mut self, etu: u8) -> Self {
+        self.guard_time = etu;
+        self
+    }
+
+    /// MAX_ITERATION: T = 0 only, how many times a NACKed character is repeated
+    /// before the iteration error is raised.  0 to 7.
+    pub fn max_iterations(mut self, iterations: u8) -> Self {
+        assert!(iterations < 8);
+        self.max_iterations = iterations;
+        self
+    }
+
+    /// INACK: T = 0 only, never NACK received characters with a parity error
+    pub fn inhibit_nack(mut self, inhibit: bool) -> Self {
+        self.inhibit_nack = inhibit;
+        self
+    }
+
+    /// DSNACK: T = 0 only, after `max_iterations` successive parity errors
+    /// accept the character instead of NACKing it again
+    pub fn disable_successive_nack(mut self, disable: bool) -> Self {
+        self.disable_successive_nack = disable;
+        self
+    }
+}
+
+pub trait Iso7816Ext<PINS>: Sized {
+    /// Configures the USART as an ISO7816 smart card interface
+    fn iso7816(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Usart<Self, PINS, Iso7816>;
+}
+
+macro_rules! iso7816 {
+    ($USARTX:ident) => {
+        impl<IO, SCK> Iso7816Ext<(IO, SCK)> for $USARTX
+        where
+            IO: TxPin<$USARTX>,
+            SCK: SckPin<$USARTX>,
+        {
+            fn iso7816(self, pins: (IO, SCK), config: Config, pmc: &mut Pmc) -> Usart<Self, (IO, SCK), Iso7816> {
+                Usart::<$USARTX, (IO, SCK), Iso7816>::reset(&self, pmc);
+
+                self.mr().write_with_zero(|w| {
+                    let w = match config.protocol {
+                        Protocol::T0 => w.usart_mode().is07816_t_0(),
+                        Protocol::T1 => w.usart_mode().is07816_t_1(),
+                    };
+
+                    let w = match config.convention {
+                        Convention::Direct => w.msbf().clear_bit().invdata().clear_bit(),
+                        Convention::Inverse => w.msbf().set_bit().invdata().set_bit(),
+                    };
+
+                    unsafe {
+                        w
+                        .usclks().mck()
+                        // Drive the card clock
+                        .clko().set_bit()
+                        .chrl()._8_bit()
+                        .par().even()
+                        .nbstop()._1_bit()
+                        .inack().bit(config.inhibit_nack)
+                        .dsnack().bit(config.disable_successive_nack)
+                        .max_iteration().bits(config.max_iterations)
+                    }
+                });
+
+                // In ISO7816 mode the baud rate generator only divides MCK down
+                // to the card clock, the bit rate is set by FI_DI_RATIO.
+                // Datasheet §35.7.1.3
+                let cd = pmc.clocks.master_clk().0.div_ceil(config.clock.0);
+                assert!(cd > 0 && cd < (1 << 16));
+                self.brgr.write(|w| unsafe { w.cd().bits(cd as u16) });
+
+                self.fidi.write(|w| unsafe { w.fi_di_ratio().bits(config.fi_di_ratio) });
+                self.ttgr.write(|w| unsafe { w.tg().bits(config.guard_time) });
+
+                let mut usart = Usart { usart: self, pins, _mode: PhantomData::<Iso7816> };
+                usart.receive();
+                usart
+            }
+        }
+
+        impl<PINS> Usart<$USARTX, PINS, Iso7816> {
+            /// Turns the link around to receive
+            pub fn receive(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.txdis().set_bit().rxen().set_bit());
+            }
+
+            /// Turns the link around to transmit.  Flush the transmitter before
+            /// switching back to receive.
+            pub fn transmit(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.rxdis().set_bit().txen().set_bit());
+            }
+
+            /// Returns the number of parity errors since the last call.  Reading
+            /// NER resets it.
+            pub fn errors(&mut self) -> u8 {
+                self.usart.ner.read().nb_errors().bits()
+            }
+
+            /// Clears the iteration error
+            pub fn reset_iterations(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.rstit().set_bit());
+            }
+
+            /// Clears the NACK error
+            pub fn reset_nack(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.rstnack().set_bit());
+            }
+
+            /// Changes the bit rate after a PPS exchange
+            pub fn set_fi_di_ratio(&mut self, ratio: u16) {
+                assert!(ratio > 0 && ratio < (1 << 11));
+                self.usart.fidi.write(|w| unsafe { w.fi_di_ratio().bits(ratio) });
+            }
+        }
+    };
+}
+
+// SCK3 isn't available on the 100 pin packages
+iso7816!(USART0);
+iso7816!(USART1);
+iso7816!(USART2);
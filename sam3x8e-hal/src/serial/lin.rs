@@ -0,0 +1,579 @@
+/*
+ *    This file (src/serial/lin.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! LIN 1.3 / 2.x master and slave nodes on the USARTs.  Datasheet §35.7.8
+//!
+//! The USART handles the break, synch and protected identifier fields of the
+//! header as well as the checksum of the response in hardware.  A master
+//! starts a frame by writing the identifier, a slave waits for a header and
+//! then decides whether to publish, subscribe to or ignore the response.
+
+use core::marker::PhantomData;
+
+use crate::pac::{usart0, usart1, usart2, usart3, USART0, USART1, USART2, USART3};
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::serial::{baud_divider, RxPin, TxPin};
+use crate::time::{Bps, U32Ext};
+
+/// The largest response the USART can handle, DLC is eight bits wide
+pub const MAX_DATA_LENGTH: usize = 256;
+
+/// Master node, sends headers
+pub struct Master;
+/// Slave node, answers headers
+pub struct Slave;
+
+/// Checksum model used for the response
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChecksumType {
+    /// LIN 2.x, the checksum covers the protected identifier and the data
+    Enhanced,
+    /// LIN 1.3, the checksum only covers the data
+    Classic,
+}
+
+/// How the number of data bytes in a response is determined
+#[derive(Clone, Copy, PartialEq)]
+pub enum DataLengthMode {
+    /// The data length is programmed for each frame
+    Dlc,
+    /// The data length is encoded in bits 4 and 5 of the identifier (LIN 1.3)
+    Identifier,
+}
+
+/// Shape of the wakeup signal sent on the bus
+#[derive(Clone, Copy, PartialEq)]
+pub enum WakeupType {
+    /// LIN 2.x, a 250 µs to 5 ms dominant pulse
+    Lin2,
+    /// LIN 1.3, the 0x80 character
+    Lin13,
+}
+
+/// What the node does with the response that follows a header
+#[derive(Clone, Copy, PartialEq)]
+pub enum NodeAction {
+    /// Transmit the response
+    Publish,
+    /// Receive the response
+    Subscribe,
+    /// Neither transmit nor receive the response
+    Ignore,
+}
+
+/// LIN errors
+#[derive(Debug)]
+pub enum Error {
+    /// LINBE: the value read back from the bus differed from what was sent
+    Bit,
+    /// LINISFE: the synch field of a header was out of tolerance (slave only)
+    InconsistentSynch,
+    /// LINIPE: the parity bits of the received identifier were wrong
+    IdentifierParity,
+    /// LINCE: the received checksum was wrong
+    Checksum,
+    /// LINSNRE: no response was received within the frame slot
+    SlaveNotResponding,
+    /// OVRE: a character was received before the previous one was read
+    Overrun,
+    /// FRAME: a stop bit was read as zero
+    Framing,
+    /// The buffer is empty, longer than `MAX_DATA_LENGTH` or, with
+    /// `DataLengthMode::Identifier`, not the length the identifier encodes
+    BufferLength,
+}
+
+/// Interrupt events
+pub enum Event {
+    /// A character has been received
+    RxReady,
+    /// The transmit holding register is empty
+    TxReady,
+    /// A break field has been sent or received
+    Break,
+    /// An identifier has been sent or received
+    Identifier,
+    /// The response has been transferred
+    TransferComplete,
+    /// Any of the LIN errors
+    Error,
+}
+
+/// LIN configuration
+pub struct Config {
+    baud_rate: Bps,
+    checksum: ChecksumType,
+    checksum_enabled: bool,
+    parity_enabled: bool,
+    data_length: DataLengthMode,
+    frame_slot_enabled: bool,
+    wakeup: WakeupType,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baud_rate: 19_200.bps(),
+            checksum: ChecksumType::Enhanced,
+            checksum_enabled: true,
+            parity_enabled: true,
+            data_length: DataLengthMode::Dlc,
+            frame_slot_enabled: true,
+            wakeup: WakeupType::Lin2,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// Generates a LIN 1.3 configuration: classic checksum, data length
+    /// encoded in the identifier and the 1.3 wakeup character
+    pub fn lin13() -> Self {
+        Config {
+            checksum: ChecksumType::Classic,
+            data_length: DataLengthMode::Identifier,
+            wakeup: WakeupType::Lin13,
+            ..Self::default()
+        }
+    }
+
+    /// Nominal bus speed, 1 to 20 kbps per the specification.  Slaves
+    /// resynchronize on every synch field.
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: ChecksumType) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// CHKDIS: when disabled the checksum is neither sent nor checked by the
+    /// hardware and has to be handled as part of the data.  See `checksum`.
+    pub fn checksum_enable(mut self, enabled: bool) -> Self {
+        self.checksum_enabled = enabled;
+        self
+    }
+
+    /// PARDIS: when disabled the identifier is sent and received verbatim,
+    /// see `protected_identifier`
+    pub fn parity_enable(mut self, enabled: bool) -> Self {
+        self.parity_enabled = enabled;
+        self
+    }
+
+    pub fn data_length(mut self, mode: DataLengthMode) -> Self {
+        self.data_length = mode;
+        self
+    }
+
+    /// FSDIS: when enabled the master waits for the end of the frame slot
+    /// before a new header can be sent
+    pub fn frame_slot_enable(mut self, enabled: bool) -> Self {
+        self.frame_slot_enabled = enabled;
+        self
+    }
+
+    pub fn wakeup(mut self, wakeup: WakeupType) -> Self {
+        self.wakeup = wakeup;
+        self
+    }
+}
+
+/// Computes the protected identifier (identifier plus parity bits P0 and P1)
+/// for a 6 bit frame identifier
+pub fn protected_identifier(id: u8) -> u8 {
+    let bit = |n: u8| (id >> n) & 1;
+
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+    (id & 0x3F) | (p0 << 6) | (p1 << 7)
+}
+
+/// Number of data bytes in a response when the length is encoded in the
+/// identifier.  See `DataLengthMode::Identifier`.
+pub fn identifier_data_length(id: u8) -> usize {
+    match (id >> 4) & 0b11 {
+        0b00 | 0b01 => 2,
+        0b10 => 4,
+        _ => 8,
+    }
+}
+
+/// Computes the checksum of a response.  Only needed when the hardware
+/// checksum has been disabled.
+pub fn checksum(checksum: ChecksumType, id: u8, data: &[u8]) -> u8 {
+    let seed = match checksum {
+        ChecksumType::Enhanced => protected_identifier(id) as u16,
+        ChecksumType::Classic => 0,
+    };
+
+    // Sum with the carry wrapped back around
+    let sum = data.iter().fold(seed, |sum, &byte| {
+        let sum = sum + byte as u16;
+        (sum & 0xFF) + (sum >> 8)
+    });
+
+    !(sum as u8)
+}
+
+/// A LIN node
+pub struct Lin<USART, PINS, NODE> {
+    usart: USART,
+    pins: PINS,
+    clocks: Clocks,
+    _node: PhantomData<NODE>,
+}
+
+pub trait LinExt<PINS>: Sized {
+    /// Configures the USART as a LIN master node
+    fn lin_master(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Lin<Self, PINS, Master>;
+
+    /// Configures the USART as a LIN slave node
+    fn lin_slave(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Lin<Self, PINS, Slave>;
+}
+
+macro_rules! lin {
+    ($USARTX:ident, $usartX:ident, $clock:ident) => {
+        impl<TX, RX> LinExt<(TX, RX)> for $USARTX
+        where
+            TX: TxPin<$USARTX>,
+            RX: RxPin<$USARTX>,
+        {
+            fn lin_master(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Lin<Self, (TX, RX), Master> {
+                let lin = Lin { usart: self, pins, clocks: pmc.clocks, _node: PhantomData };
+                lin.configure(&config, pmc, true);
+                lin
+            }
+
+            fn lin_slave(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Lin<Self, (TX, RX), Slave> {
+                let lin = Lin { usart: self, pins, clocks: pmc.clocks, _node: PhantomData };
+                lin.configure(&config, pmc, false);
+                lin
+            }
+        }
+
+        impl<PINS, NODE> Lin<$USARTX, PINS, NODE> {
+            fn configure(&self, config: &Config, pmc: &mut Pmc, master: bool) {
+                pmc.enable_clock(PeripheralClock::$clock);
+
+                let usart = &self.usart;
+
+                // Disable write protection
+                usart.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+                usart.cr().write_with_zero(|w|
+                    w
+                    .rstrx().set_bit()
+                    .rsttx().set_bit()
+                    .rxdis().set_bit()
+                    .txdis().set_bit()
+                    .rststa().set_bit()
+                );
+
+                // The character format is fixed by the LIN mode: 8 data bits,
+                // no parity, one stop bit.  Datasheet §35.7.8.1
+                usart.mr().write_with_zero(|w| {
+                    let w = w.usclks().mck().over().clear_bit();
+                    match master {
+                        true => w.usart_mode().lin_master(),
+                        false => w.usart_mode().lin_slave(),
+                    }
+                });
+
+                let (cd, fp) = baud_divider(&self.clocks, config.baud_rate);
+                usart.brgr.write(|w| unsafe { w.cd().bits(cd).fp().bits(fp) });
+
+                usart.linmr.write(|w| {
+                    let w = w
+                        .nact().ignore()
+                        .chkdis().bit(!config.checksum_enabled)
+                        .pardis().bit(!config.parity_enabled)
+                        .fsdis().bit(!config.frame_slot_enabled);
+
+                    let w = match config.checksum {
+                        ChecksumType::Enhanced => w.chktyp().clear_bit(),
+                        ChecksumType::Classic => w.chktyp().set_bit(),
+                    };
+
+                    let w = match config.data_length {
+                        DataLengthMode::Dlc => w.dlm().clear_bit(),
+                        DataLengthMode::Identifier => w.dlm().set_bit(),
+                    };
+
+                    match config.wakeup {
+                        WakeupType::Lin2 => w.wkuptyp().clear_bit(),
+                        WakeupType::Lin13 => w.wkuptyp().set_bit(),
+                    }
+                });
+
+                usart.cr().write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+            }
+
+            /// Selects the action for the response to `id`.  `length` is the
+            /// number of data bytes, which must match `identifier_data_length`
+            /// when the length is encoded in the identifier.
+            fn set_action(&mut self, action: NodeAction, id: u8, length: usize) -> Result<(), Error> {
+                let from_identifier = self.usart.linmr.read().dlm().bit_is_set();
+                let valid = match from_identifier {
+                    true => length == identifier_data_length(id),
+                    false => length != 0 && length <= MAX_DATA_LENGTH,
+                };
+                if !valid {
+                    return Err(Error::BufferLength);
+                }
+
+                self.usart.linmr.modify(|_, w| {
+                    let w = match from_identifier {
+                        true => w,
+                        false => unsafe { w.dlc().bits((length - 1) as u8) },
+                    };
+                    match action {
+                        NodeAction::Publish => w.nact().publish(),
+                        NodeAction::Subscribe => w.nact().subscribe(),
+                        NodeAction::Ignore => w.nact().ignore(),
+                    }
+                });
+
+                Ok(())
+            }
+
+            /// Checks the status register for errors, clearing them if any are found
+            fn check_errors(&self, csr: &$usartX::csr_lin_mode::R) -> Result<(), Error> {
+                let error = if csr.linbe().bit_is_set() {
+                    Some(Error::Bit)
+                } else if csr.linisfe().bit_is_set() {
+                    Some(Error::InconsistentSynch)
+                } else if csr.linipe().bit_is_set() {
+                    Some(Error::IdentifierParity)
+                } else if csr.lince().bit_is_set() {
+                    Some(Error::Checksum)
+                } else if csr.linsnre().bit_is_set() {
+                    Some(Error::SlaveNotResponding)
+                } else if csr.ovre().bit_is_set() {
+                    Some(Error::Overrun)
+                } else if csr.frame().bit_is_set() {
+                    Some(Error::Framing)
+                } else {
+                    None
+                };
+
+                match error {
+                    Some(error) => {
+                        self.clear_status();
+                        Err(error)
+                    },
+                    None => Ok(()),
+                }
+            }
+
+            /// Busy waits until `ready` returns true or an error is flagged
+            fn wait_for<F>(&self, ready: F) -> Result<(), Error>
+            where
+                F: Fn(&$usartX::csr_lin_mode::R) -> bool,
+            {
+                loop {
+                    let csr = self.usart.csr_lin_mode().read();
+                    self.check_errors(&csr)?;
+                    if ready(&csr) {
+                        return Ok(());
+                    }
+                }
+            }
+
+            fn write_response(&mut self, data: &[u8]) -> Result<(), Error> {
+                for byte in data {
+                    self.wait_for(|csr| csr.txrdy().bit_is_set())?;
+                    self.usart.thr.write_with_zero(|w| unsafe { w.txchr().bits(*byte as u16) });
+                }
+
+                self.wait_for(|csr| csr.lintc().bit_is_set())?;
+                self.clear_status();
+                Ok(())
+            }
+
+            fn read_response(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                for byte in buffer.iter_mut() {
+                    self.wait_for(|csr| csr.rxrdy().bit_is_set())?;
+                    *byte = self.usart.rhr.read().rxchr().bits() as u8;
+                }
+
+                self.wait_for(|csr| csr.lintc().bit_is_set())?;
+                self.clear_status();
+                Ok(())
+            }
+
+            /// Resets the LIN status and error flags
+            pub fn clear_status(&self) {
+                self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+            }
+
+            /// Returns true if a break field has been sent or received since the
+            /// status was last cleared
+            pub fn is_break(&self) -> bool {
+                self.usart.csr_lin_mode().read().linbk().bit_is_set()
+            }
+
+            /// Sends a wakeup signal on the bus
+            pub fn wakeup(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.linwkup().set_bit());
+            }
+
+            /// Aborts the frame in progress
+            pub fn abort(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.linabt().set_bit());
+                self.clear_status();
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                let ier = self.usart.ier_lin_mode();
+                match event {
+                    Event::RxReady => ier.write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => ier.write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::Break => ier.write_with_zero(|w| w.linbk().set_bit()),
+                    Event::Identifier => ier.write_with_zero(|w| w.linid().set_bit()),
+                    Event::TransferComplete => ier.write_with_zero(|w| w.lintc().set_bit()),
+                    Event::Error => ier.write_with_zero(|w|
+                        w
+                        .linbe().set_bit()
+                        .linisfe().set_bit()
+                        .linipe().set_bit()
+                        .lince().set_bit()
+                        .linsnre().set_bit()
+                    ),
+                }
+            }
+
+            /// Stops listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                let idr = self.usart.idr_lin_mode();
+                match event {
+                    Event::RxReady => idr.write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => idr.write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::Break => idr.write_with_zero(|w| w.linbk().set_bit()),
+                    Event::Identifier => idr.write_with_zero(|w| w.linid().set_bit()),
+                    Event::TransferComplete => idr.write_with_zero(|w| w.lintc().set_bit()),
+                    Event::Error => idr.write_with_zero(|w|
+                        w
+                        .linbe().set_bit()
+                        .linisfe().set_bit()
+                        .linipe().set_bit()
+                        .lince().set_bit()
+                        .linsnre().set_bit()
+                    ),
+                }
+            }
+
+            /// Releases the USART peripheral and pins
+            pub fn free(self) -> ($USARTX, PINS) {
+                (self.usart, self.pins)
+            }
+        }
+
+        impl<PINS> Lin<$USARTX, PINS, Master> {
+            /// Starts a frame by sending its header.  With parity enabled only
+            /// the low six bits of `id` are used, the hardware adds P0 and P1.
+            fn send_header(&mut self, id: u8) -> Result<(), Error> {
+                self.wait_for(|csr| csr.txrdy().bit_is_set())?;
+                self.clear_status();
+                self.usart.linir.write(|w| unsafe { w.idchr().bits(id) });
+                Ok(())
+            }
+
+            /// Sends a header followed by a response published by this node
+            pub fn write_frame(&mut self, id: u8, data: &[u8]) -> Result<(), Error> {
+                self.set_action(NodeAction::Publish, id, data.len())?;
+                self.send_header(id)?;
+                self.write_response(data)
+            }
+
+            /// Sends a header and receives the response published by a slave
+            pub fn read_frame(&mut self, id: u8, buffer: &mut [u8]) -> Result<(), Error> {
+                self.set_action(NodeAction::Subscribe, id, buffer.len())?;
+                self.send_header(id)?;
+                self.read_response(buffer)
+            }
+
+            /// Sends a header without taking part in the response, e.g. for
+            /// slave to slave communication
+            pub fn send_header_only(&mut self, id: u8) -> Result<(), Error> {
+                self.usart.linmr.modify(|_, w| w.nact().ignore());
+                self.send_header(id)?;
+                self.wait_for(|csr| csr.linid().bit_is_set())
+            }
+        }
+
+        impl<PINS> Lin<$USARTX, PINS, Slave> {
+            /// Returns the identifier of a received header.  Break detection and
+            /// synchronization to the master happen in hardware.  Once this
+            /// returns one of `respond`, `receive` or `ignore` must be called
+            /// before the response starts.
+            pub fn identifier(&mut self) -> nb::Result<u8, Error> {
+                let csr = self.usart.csr_lin_mode().read();
+                self.check_errors(&csr)?;
+
+                if csr.linid().bit_is_set() {
+                    Ok(self.usart.linir.read().idchr().bits() & 0x3F)
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            /// Publishes the response to the last header
+            pub fn respond(&mut self, data: &[u8]) -> Result<(), Error> {
+                let id = self.usart.linir.read().idchr().bits();
+                self.set_action(NodeAction::Publish, id, data.len())?;
+                self.write_response(data)
+            }
+
+            /// Subscribes to the response to the last header
+            pub fn receive(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+                let id = self.usart.linir.read().idchr().bits();
+                self.set_action(NodeAction::Subscribe, id, buffer.len())?;
+                self.read_response(buffer)
+            }
+
+            /// Ignores the response to the last header
+            pub fn ignore(&mut self) {
+                self.usart.linmr.modify(|_, w| w.nact().ignore());
+                self.clear_status();
+            }
+
+            /// Returns the baud rate measured on the last synch field
+            pub fn measured_baud_rate(&self) -> Bps {
+                let linbrr = self.usart.linbrr.read();
+                // Baud rate = MCK / (16 * (LINCD + LINFP / 8))
+                let eighths = ((linbrr.lincd().bits() as u32) << 3) | linbrr.linfp().bits() as u32;
+                if eighths == 0 {
+                    return 0.bps();
+                }
+                (self.clocks.master_clk().0 * 8 / (16 * eighths)).bps()
+            }
+        }
+    };
+}
+
+lin!(USART0, usart0, Usart0);
+lin!(USART1, usart1, Usart1);
+lin!(USART2, usart2, Usart2);
+lin!(USART3, usart3, Usart3);
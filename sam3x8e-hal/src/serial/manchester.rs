@@ -0,0 +1,242 @@
+/*
+ *    This file (src/serial/manchester.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Manchester encoder / decoder.  Datasheet §35.7.3
+//!
+//! Every character is preceded by an optional preamble and a start frame
+//! delimiter, either a single start bit or a three bit period sync pattern
+//! that also tells data and command characters apart.
+
+use core::marker::PhantomData;
+
+use crate::pac::{USART0, USART1, USART2, USART3};
+use crate::pmc::Pmc;
+use crate::serial::{baud_divider, usart::Usart, Error, RxPin, TxPin};
+use crate::time::{Bps, U32Ext};
+
+/// Manchester mode
+pub struct Manchester;
+
+/// Preamble sent before / expected ahead of every character
+#[derive(Clone, Copy, PartialEq)]
+pub enum PreamblePattern {
+    AllOne,
+    AllZero,
+    ZeroOne,
+    OneZero,
+}
+
+/// Encoding of a bit
+#[derive(Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// A zero is a zero to one transition, this is the IEEE 802.3 convention
+    ZeroIsRising,
+    /// A zero is a one to zero transition
+    ZeroIsFalling,
+}
+
+/// Start of a character
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameDelimiter {
+    /// A single start bit
+    OneBit,
+    /// A data or command sync pattern, see `write_command`
+    Sync,
+}
+
+/// Kind of sync pattern that started a character
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SyncType {
+    Data,
+    Command,
+}
+
+/// Manchester configuration
+pub struct Config {
+    baud_rate: Bps,
+    tx_preamble: (PreamblePattern, u8),
+    rx_preamble: (PreamblePattern, u8),
+    tx_polarity: Polarity,
+    rx_polarity: Polarity,
+    delimiter: FrameDelimiter,
+    drift_compensation: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baud_rate: 9_600.bps(),
+            tx_preamble: (PreamblePattern::AllOne, 0),
+            rx_preamble: (PreamblePattern::AllOne, 0),
+            tx_polarity: Polarity::ZeroIsRising,
+            rx_polarity: Polarity::ZeroIsRising,
+            delimiter: FrameDelimiter::OneBit,
+            drift_compensation: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// TX_PP / TX_PL: pattern and length in bit periods (0 to 15) of the
+    /// transmitted preamble
+    pub fn tx_preamble(mut self, pattern: PreamblePattern, length: u8) -> Self {
+        assert!(length < 16);
+        self.tx_preamble = (pattern, length);
+        self
+    }
+
+    /// RX_PP / RX_PL: pattern and length in bit periods (0 to 15) of the
+    /// expected preamble
+    pub fn rx_preamble(mut self, pattern: PreamblePattern, length: u8) -> Self {
+        assert!(length < 16);
+        self.rx_preamble = (pattern, length);
+        self
+    }
+
+    pub fn tx_polarity(mut self, polarity: Polarity) -> Self {
+        self.tx_polarity = polarity;
+        self
+    }
+
+    pub fn rx_polarity(mut self, polarity: Polarity) -> Self {
+        self.rx_polarity = polarity;
+        self
+    }
+
+    pub fn delimiter(mut self, delimiter: FrameDelimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// DRIFT: let the decoder track a transmitter whose clock drifts
+    pub fn drift_compensation(mut self, enabled: bool) -> Self {
+        self.drift_compensation = enabled;
+        self
+    }
+}
+
+pub trait ManchesterExt<PINS>: Sized {
+    /// Configures the USART with the Manchester encoder and decoder enabled
+    fn manchester(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Usart<Self, PINS, Manchester>;
+}
+
+macro_rules! manchester {
+    ($USARTX:ident) => {
+        impl<TX, RX> ManchesterExt<(TX, RX)> for $USARTX
+        where
+            TX: TxPin<$USARTX>,
+            RX: RxPin<$USARTX>,
+        {
+            fn manchester(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Usart<Self, (TX, RX), Manchester> {
+                Usart::<$USARTX, (TX, RX), Manchester>::reset(&self, pmc);
+
+                self.mr().write_with_zero(|w| {
+                    let w = w
+                        .usart_mode().normal()
+                        .usclks().mck()
+                        .chrl()._8_bit()
+                        .par().no()
+                        .nbstop()._1_bit()
+                        // Drift compensation needs 16x oversampling
+                        .over().clear_bit()
+                        .man().set_bit();
+
+                    match config.delimiter {
+                        FrameDelimiter::OneBit => w.onebit().set_bit(),
+                        FrameDelimiter::Sync => w.onebit().clear_bit(),
+                    }
+                });
+
+                let (cd, fp) = baud_divider(&pmc.clocks, config.baud_rate);
+                self.brgr.write(|w| unsafe { w.cd().bits(cd).fp().bits(fp) });
+
+                self.man.write(|w| {
+                    let (tx_pattern, tx_length) = config.tx_preamble;
+                    let (rx_pattern, rx_length) = config.rx_preamble;
+
+                    let w = unsafe { w.tx_pl().bits(tx_length).rx_pl().bits(rx_length) };
+
+                    let w = match tx_pattern {
+                        PreamblePattern::AllOne => w.tx_pp().all_one(),
+                        PreamblePattern::AllZero => w.tx_pp().all_zero(),
+                        PreamblePattern::ZeroOne => w.tx_pp().zero_one(),
+                        PreamblePattern::OneZero => w.tx_pp().one_zero(),
+                    };
+
+                    let w = match rx_pattern {
+                        PreamblePattern::AllOne => w.rx_pp().all_one(),
+                        PreamblePattern::AllZero => w.rx_pp().all_zero(),
+                        PreamblePattern::ZeroOne => w.rx_pp().zero_one(),
+                        PreamblePattern::OneZero => w.rx_pp().one_zero(),
+                    };
+
+                    w
+                    .tx_mpol().bit(config.tx_polarity == Polarity::ZeroIsFalling)
+                    .rx_mpol().bit(config.rx_polarity == Polarity::ZeroIsFalling)
+                    .drift().bit(config.drift_compensation)
+                    // Must be written as one.  Datasheet §35.8.17
+                    .one().set_bit()
+                });
+
+                let mut usart = Usart { usart: self, pins, _mode: PhantomData };
+                usart.enable();
+                usart
+            }
+        }
+
+        impl<PINS> Usart<$USARTX, PINS, Manchester> {
+            /// Sends a character preceded by a command sync pattern.  Only
+            /// meaningful with `FrameDelimiter::Sync`, `write` sends data syncs.
+            pub fn write_command(&mut self, byte: u8) -> nb::Result<(), Error> {
+                if self.usart.csr().read().txrdy().bit_is_set() {
+                    self.usart.thr.write_with_zero(|w| unsafe { w.txchr().bits(byte as u16).txsynh().set_bit() });
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            /// Reads a character along with the kind of sync pattern that
+            /// preceded it
+            pub fn read_with_sync(&mut self) -> nb::Result<(u8, SyncType), Error> {
+                self.read_rhr().map(|rhr| {
+                    // RXSYNH, bit 15
+                    let sync = match rhr & (1 << 15) {
+                        0 => SyncType::Data,
+                        _ => SyncType::Command,
+                    };
+
+                    (rhr as u8, sync)
+                })
+            }
+        }
+    };
+}
+
+manchester!(USART0);
+manchester!(USART1);
+manchester!(USART2);
+manchester!(USART3);
@@ -0,0 +1,216 @@
+/*
+ *    This file (src/serial/uart.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! UART driver.  Datasheet §34
+//!
+//! The UART is a two pin, 8 data bit, 1 stop bit asynchronous serial port.  On
+//! the Arduino Due it's wired to the programming port's USB to serial bridge.
+
+use crate::hal::serial;
+use crate::pac::{Interrupt, UART};
+use crate::pdc;
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::serial::{Error, Event, Listen, Parity, RxPin, TxPin};
+use crate::time::{Bps, U32Ext};
+
+/// UART configuration
+pub struct Config {
+    baud_rate: Bps,
+    parity: Parity,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baud_rate: 115_200.bps(),
+            parity: Parity::None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+}
+
+/// The UART
+pub struct Uart<PINS> {
+    uart: UART,
+    pins: PINS,
+}
+
+pub trait UartExt<PINS> {
+    /// Configures the UART
+    fn serial(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Uart<PINS>;
+}
+
+impl<TX, RX> UartExt<(TX, RX)> for UART
+where
+    TX: TxPin<UART>,
+    RX: RxPin<UART>,
+{
+    fn serial(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Uart<(TX, RX)> {
+        pmc.enable_clock(PeripheralClock::Uart);
+
+        self.cr.write_with_zero(|w|
+            w
+            .rstrx().set_bit()
+            .rsttx().set_bit()
+            .rxdis().set_bit()
+            .txdis().set_bit()
+            .rststa().set_bit()
+        );
+
+        self.mr.write(|w| {
+            let w = w.chmode().normal();
+            match config.parity {
+                Parity::None => w.par().no(),
+                Parity::Even => w.par().even(),
+                Parity::Odd => w.par().odd(),
+                Parity::Space => w.par().space(),
+                Parity::Mark => w.par().mark(),
+            }
+        });
+
+        // Baud rate = MCK / (16 * CD), there's no fractional part.  Datasheet §34.5.1
+        let baud_rate = config.baud_rate.0;
+        let cd = (pmc.clocks.master_clk().0 + 8 * baud_rate) / (16 * baud_rate);
+        assert!(cd > 0 && cd < (1 << 16));
+        self.brgr.write(|w| unsafe { w.cd().bits(cd as u16) });
+
+        self.cr.write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+
+        Uart { uart: self, pins }
+    }
+}
+
+impl<PINS> Uart<PINS> {
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::RxReady => self.uart.ier.write_with_zero(|w| w.rxrdy().set_bit()),
+            Event::TxReady => self.uart.ier.write_with_zero(|w| w.txrdy().set_bit()),
+            Event::TxEmpty => self.uart.ier.write_with_zero(|w| w.txempty().set_bit()),
+        }
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::RxReady => self.uart.idr.write_with_zero(|w| w.rxrdy().set_bit()),
+            Event::TxReady => self.uart.idr.write_with_zero(|w| w.txrdy().set_bit()),
+            Event::TxEmpty => self.uart.idr.write_with_zero(|w| w.txempty().set_bit()),
+        }
+    }
+
+    /// Releases the UART peripheral and pins
+    pub fn free(self) -> (UART, PINS) {
+        self.uart.cr.write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+        (self.uart, self.pins)
+    }
+}
+
+impl<PINS> Listen for Uart<PINS> {
+    const INTERRUPT: Interrupt = Interrupt::UART;
+
+    fn listen(&mut self, event: Event) {
+        Uart::listen(self, event)
+    }
+
+    fn unlisten(&mut self, event: Event) {
+        Uart::unlisten(self, event)
+    }
+}
+
+impl<PINS> pdc::Receive for Uart<PINS> {
+    type Word = u8;
+    type Channel = UART;
+
+    fn rx_channel(&self) -> &UART {
+        &self.uart
+    }
+}
+
+impl<PINS> pdc::Transmit for Uart<PINS> {
+    type Word = u8;
+    type Channel = UART;
+
+    fn tx_channel(&self) -> &UART {
+        &self.uart
+    }
+}
+
+impl<PINS> serial::Read<u8> for Uart<PINS> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        let sr = self.uart.sr.read();
+
+        let error = if sr.ovre().bit_is_set() {
+            Some(Error::Overrun)
+        } else if sr.frame().bit_is_set() {
+            Some(Error::Framing)
+        } else if sr.pare().bit_is_set() {
+            Some(Error::Parity)
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            self.uart.cr.write_with_zero(|w| w.rststa().set_bit());
+            return Err(nb::Error::Other(error));
+        }
+
+        if sr.rxrdy().bit_is_set() {
+            Ok(self.uart.rhr.read().rxchr().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<PINS> serial::Write<u8> for Uart<PINS> {
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+        if self.uart.sr.read().txrdy().bit_is_set() {
+            self.uart.thr.write_with_zero(|w| unsafe { w.txchr().bits(byte) });
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        if self.uart.sr.read().txempty().bit_is_set() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ *    This file (src/serial/usart.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Character oriented USART driver shared by the USART modes.  Datasheet §35
+//!
+//! The mode (normal, IrDA, ISO7816, Manchester...) is fixed when the USART is
+//! configured and is carried in the type.  The normal asynchronous mode is
+//! configured here, see the other serial modules for the remaining modes.
+
+use core::marker::PhantomData;
+
+use crate::dma::{self, Handshake};
+use crate::hal::serial;
+use crate::pac::{Interrupt, USART0, USART1, USART2, USART3};
+use crate::pdc;
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::serial::{baud_divider, Error, Event, Listen, Parity, RxPin, TxPin};
+use crate::time::{Bps, U32Ext};
+
+/// Normal asynchronous mode
+pub struct Normal;
+
+/// Number of data bits in a character
+#[derive(Clone, Copy, PartialEq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+/// Number of stop bits after a character
+#[derive(Clone, Copy, PartialEq)]
+pub enum StopBits {
+    One,
+    OnePointFive,
+    Two,
+}
+
+/// Asynchronous USART configuration
+pub struct Config {
+    baud_rate: Bps,
+    data_bits: DataBits,
+    parity: Parity,
+    stop_bits: StopBits,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baud_rate: 115_200.bps(),
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn baud_rate(mut self, baud_rate: Bps) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+        self.data_bits = data_bits;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+}
+
+/// A USART configured for `MODE`
+pub struct Usart<USART, PINS, MODE> {
+    pub(super) usart: USART,
+    pub(super) pins: PINS,
+    pub(super) _mode: PhantomData<MODE>,
+}
+
+pub trait UsartExt<PINS>: Sized {
+    /// Configures the USART for normal asynchronous operation
+    fn serial(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Usart<Self, PINS, Normal>;
+}
+
+macro_rules! usart {
+    ($USARTX:ident, $clock:ident) => {
+        impl<TX, RX> UsartExt<(TX, RX)> for $USARTX
+        where
+            TX: TxPin<$USARTX>,
+            RX: RxPin<$USARTX>,
+        {
+            fn serial(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Usart<Self, (TX, RX), Normal> {
+                Usart::<$USARTX, (TX, RX), Normal>::reset(&self, pmc);
+
+                self.mr().write_with_zero(|w| {
+                    let w = w
+                        .usart_mode().normal()
+                        .usclks().mck()
+                        .over().clear_bit();
+
+                    let w = match config.data_bits {
+                        DataBits::Five => w.chrl()._5_bit(),
+                        DataBits::Six => w.chrl()._6_bit(),
+                        DataBits::Seven => w.chrl()._7_bit(),
+                        DataBits::Eight => w.chrl()._8_bit(),
+                    };
+
+                    let w = match config.parity {
+                        Parity::None => w.par().no(),
+                        Parity::Even => w.par().even(),
+                        Parity::Odd => w.par().odd(),
+                        Parity::Space => w.par().space(),
+                        Parity::Mark => w.par().mark(),
+                    };
+
+                    match config.stop_bits {
+                        StopBits::One => w.nbstop()._1_bit(),
+                        StopBits::OnePointFive => w.nbstop()._1_5_bit(),
+                        StopBits::Two => w.nbstop()._2_bit(),
+                    }
+                });
+
+                let (cd, fp) = baud_divider(&pmc.clocks, config.baud_rate);
+                self.brgr.write(|w| unsafe { w.cd().bits(cd).fp().bits(fp) });
+
+                let mut usart = Usart { usart: self, pins, _mode: PhantomData };
+                usart.enable();
+                usart
+            }
+        }
+
+        impl<PINS, MODE> Usart<$USARTX, PINS, MODE> {
+            /// Enables the peripheral clock and leaves the USART with both the
+            /// receiver and transmitter reset and disabled, ready for the mode
+            /// registers to be written
+            pub(super) fn reset(usart: &$USARTX, pmc: &mut Pmc) {
+                pmc.enable_clock(PeripheralClock::$clock);
+
+                // Disable write protection
+                usart.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+                usart.cr().write_with_zero(|w|
+                    w
+                    .rstrx().set_bit()
+                    .rsttx().set_bit()
+                    .rxdis().set_bit()
+                    .txdis().set_bit()
+                    .rststa().set_bit()
+                );
+            }
+
+            /// Enables the receiver and the transmitter
+            pub(super) fn enable(&mut self) {
+                self.usart.cr().write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+            }
+
+            /// Returns the raw receive holding register once a character has
+            /// been received.  Reading it clears RXRDY.
+            pub(super) fn read_rhr(&mut self) -> nb::Result<u32, Error> {
+                let csr = self.usart.csr().read();
+
+                let error = if csr.ovre().bit_is_set() {
+                    Some(Error::Overrun)
+                } else if csr.frame().bit_is_set() {
+                    Some(Error::Framing)
+                } else if csr.pare().bit_is_set() {
+                    Some(Error::Parity)
+                } else if csr.manerr().bit_is_set() {
+                    Some(Error::Manchester)
+                } else {
+                    None
+                };
+
+                if let Some(error) = error {
+                    self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                    return Err(nb::Error::Other(error));
+                }
+
+                if csr.rxrdy().bit_is_set() {
+                    Ok(self.usart.rhr.read().bits())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                match event {
+                    Event::RxReady => self.usart.ier().write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => self.usart.ier().write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::TxEmpty => self.usart.ier().write_with_zero(|w| w.txempty().set_bit()),
+                }
+            }
+
+            /// Stops listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                match event {
+                    Event::RxReady => self.usart.idr().write_with_zero(|w| w.rxrdy().set_bit()),
+                    Event::TxReady => self.usart.idr().write_with_zero(|w| w.txrdy().set_bit()),
+                    Event::TxEmpty => self.usart.idr().write_with_zero(|w| w.txempty().set_bit()),
+                }
+            }
+
+            /// Releases the USART peripheral and pins
+            pub fn free(self) -> ($USARTX, PINS) {
+                self.usart.cr().write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+                (self.usart, self.pins)
+            }
+        }
+
+        impl<PINS, MODE> Listen for Usart<$USARTX, PINS, MODE> {
+            const INTERRUPT: Interrupt = Interrupt::$USARTX;
+
+            fn listen(&mut self, event: Event) {
+                <Usart<$USARTX, PINS, MODE>>::listen(self, event)
+            }
+
+            fn unlisten(&mut self, event: Event) {
+                <Usart<$USARTX, PINS, MODE>>::unlisten(self, event)
+            }
+        }
+
+        impl<PINS, MODE> pdc::Receive for Usart<$USARTX, PINS, MODE> {
+            type Word = u8;
+            type Channel = $USARTX;
+
+            fn rx_channel(&self) -> &$USARTX {
+                &self.usart
+            }
+        }
+
+        impl<PINS, MODE> pdc::Transmit for Usart<$USARTX, PINS, MODE> {
+            type Word = u8;
+            type Channel = $USARTX;
+
+            fn tx_channel(&self) -> &$USARTX {
+                &self.usart
+            }
+        }
+
+        impl<PINS, MODE> serial::Read<u8> for Usart<$USARTX, PINS, MODE> {
+            type Error = Error;
+
+            fn read(&mut self) -> nb::Result<u8, Error> {
+                self.read_rhr().map(|rhr| rhr as u8)
+            }
+        }
+
+        impl<PINS, MODE> serial::Write<u8> for Usart<$USARTX, PINS, MODE> {
+            type Error = Error;
+
+            fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                let csr = self.usart.csr().read();
+
+                // Only set in ISO7816 mode, these have their own reset bits
+                if csr.iter().bit_is_set() {
+                    self.usart.cr().write_with_zero(|w| w.rstit().set_bit());
+                    return Err(nb::Error::Other(Error::Iteration));
+                }
+
+                if csr.nack().bit_is_set() {
+                    self.usart.cr().write_with_zero(|w| w.rstnack().set_bit());
+                    return Err(nb::Error::Other(Error::Nack));
+                }
+
+                if csr.txrdy().bit_is_set() {
+                    self.usart.thr.write_with_zero(|w| unsafe { w.txchr().bits(byte as u16) });
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+
+            fn flush(&mut self) -> nb::Result<(), Error> {
+                if self.usart.csr().read().txempty().bit_is_set() {
+                    Ok(())
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+    };
+}
+
+usart!(USART0, Usart0);
+usart!(USART1, Usart1);
+usart!(USART2, Usart2);
+usart!(USART3, Usart3);
+
+// Only USART0 and USART1 have DMAC handshake interfaces, the others use the PDC
+macro_rules! usart_dma {
+    ($USARTX:ident, $tx:ident, $rx:ident) => {
+        unsafe impl<PINS, MODE> dma::PeripheralSource for Usart<$USARTX, PINS, MODE> {
+            type Word = u8;
+            const HANDSHAKE: Handshake = Handshake::$rx;
+
+            fn source_address(&self) -> u32 {
+                &self.usart.rhr as *const _ as u32
+            }
+        }
+
+        unsafe impl<PINS, MODE> dma::PeripheralDestination for Usart<$USARTX, PINS, MODE> {
+            type Word = u8;
+            const HANDSHAKE: Handshake = Handshake::$tx;
+
+            fn destination_address(&self) -> u32 {
+                &self.usart.thr as *const _ as u32
+            }
+        }
+    };
+}
+
+usart_dma!(USART0, Usart0Tx, Usart0Rx);
+usart_dma!(USART1, Usart1Tx, Usart1Rx);
@@ -0,0 +1,432 @@
+/*
+ *    This file (src/spi.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SPI master.  Datasheet §32
+//!
+//! Each of the four chip selects (NPCS0-3) has its own chip select register
+//! holding the clock mode, baud rate divider, delays and transfer size for the
+//! device behind it.  In fixed peripheral select mode `select` picks the
+//! device, in variable mode the chip select travels with every transfer.
+
+use crate::dma::{self, Handshake};
+use crate::gpio::{PeripheralA, PeripheralB};
+use crate::gpio::pioa::{PA25, PA26, PA27, PA28, PA29};
+use crate::gpio::piob::{PB20, PB21, PB23};
+use crate::hal::blocking;
+use crate::hal::spi::{self, FullDuplex, Mode, Phase, Polarity};
+use crate::pac::SPI0;
+use crate::pmc::{Clocks, PeripheralClock, Pmc};
+use crate::time::{Hertz, U32Ext};
+
+pub mod shared;
+pub mod slave;
+
+/// SPI errors
+#[derive(Debug)]
+pub enum Error {
+    /// A word was received before the previous one was read
+    Overrun,
+    /// Another master drove NSS low
+    ModeFault,
+    /// Slave: the master clocked a word out before one was written
+    Underrun,
+}
+
+/// Hardware chip select lines
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChipSelect {
+    Cs0,
+    Cs1,
+    Cs2,
+    Cs3,
+}
+
+impl ChipSelect {
+    fn index(self) -> usize {
+        match self {
+            ChipSelect::Cs0 => 0,
+            ChipSelect::Cs1 => 1,
+            ChipSelect::Cs2 => 2,
+            ChipSelect::Cs3 => 3,
+        }
+    }
+
+    /// PCS value driving just this line low, without the external decoder.
+    /// Datasheet §32.8.2
+    fn pcs(self) -> u8 {
+        match self {
+            ChipSelect::Cs0 => 0b1110,
+            ChipSelect::Cs1 => 0b1101,
+            ChipSelect::Cs2 => 0b1011,
+            ChipSelect::Cs3 => 0b0111,
+        }
+    }
+}
+
+/// How the chip select is picked
+#[derive(Clone, Copy, PartialEq)]
+pub enum PeripheralSelect {
+    /// Set by `select`, the transmit data register only holds data
+    Fixed,
+    /// Sent along with every word, see `Spi::send_to`
+    Variable,
+}
+
+pub trait SckPin<SPI> {}
+pub trait MisoPin<SPI> {}
+pub trait MosiPin<SPI> {}
+
+/// A hardware chip select output
+pub trait NpcsPin<SPI> {
+    const CS: ChipSelect;
+}
+
+impl SckPin<SPI0> for PA27<PeripheralA> {}
+impl MisoPin<SPI0> for PA25<PeripheralA> {}
+impl MosiPin<SPI0> for PA26<PeripheralA> {}
+
+impl NpcsPin<SPI0> for PA28<PeripheralA> { const CS: ChipSelect = ChipSelect::Cs0; }
+impl NpcsPin<SPI0> for PA29<PeripheralA> { const CS: ChipSelect = ChipSelect::Cs1; }
+impl NpcsPin<SPI0> for PB20<PeripheralB> { const CS: ChipSelect = ChipSelect::Cs1; }
+impl NpcsPin<SPI0> for PB21<PeripheralB> { const CS: ChipSelect = ChipSelect::Cs2; }
+impl NpcsPin<SPI0> for PB23<PeripheralB> { const CS: ChipSelect = ChipSelect::Cs3; }
+
+/// Controller wide configuration
+pub struct Config {
+    peripheral_select: PeripheralSelect,
+    delay_between_chip_selects: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            peripheral_select: PeripheralSelect::Fixed,
+            delay_between_chip_selects: 0,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn peripheral_select(mut self, peripheral_select: PeripheralSelect) -> Self {
+        self.peripheral_select = peripheral_select;
+        self
+    }
+
+    /// DLYBCS: master clock periods between one chip select going inactive
+    /// and the next going active.  Values below 6 mean 6.
+    pub fn delay_between_chip_selects(mut self, delay: u8) -> Self {
+        self.delay_between_chip_selects = delay;
+        self
+    }
+}
+
+/// Per chip select configuration
+#[derive(Clone, Copy)]
+pub struct ChipSelectConfig {
+    mode: Mode,
+    frequency: Hertz,
+    bits: u8,
+    delay_before_clock: u8,
+    delay_between_transfers: u8,
+    keep_active: bool,
+}
+
+impl Default for ChipSelectConfig {
+    fn default() -> ChipSelectConfig {
+        ChipSelectConfig {
+            mode: spi::MODE_0,
+            frequency: 1_000_000.hz(),
+            bits: 8,
+            delay_before_clock: 0,
+            delay_between_transfers: 0,
+            keep_active: false,
+        }
+    }
+}
+
+impl ChipSelectConfig {
+    pub fn new() -> Self {
+        ChipSelectConfig {..Self::default()}
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// SPCK frequency, rounded down to the nearest MCK / SCBR
+    pub fn frequency<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        self.frequency = frequency.into();
+        self
+    }
+
+    /// Bits per transfer, 8 to 16
+    pub fn bits(mut self, bits: u8) -> Self {
+        assert!((8..=16).contains(&bits));
+        self.bits = bits;
+        self
+    }
+
+    /// DLYBS: master clock periods from the chip select going active to the
+    /// first clock edge.  Zero means half an SPCK period.
+    pub fn delay_before_clock(mut self, delay: u8) -> Self {
+        self.delay_before_clock = delay;
+        self
+    }
+
+    /// DLYBCT: delay between consecutive transfers in units of 32 master clock
+    /// periods
+    pub fn delay_between_transfers(mut self, delay: u8) -> Self {
+        self.delay_between_transfers = delay;
+        self
+    }
+
+    /// CSAAT: keep the chip select active after the last transfer until
+    /// another device is selected or `Spi::last_transfer` is called
+    pub fn keep_active(mut self, keep_active: bool) -> Self {
+        self.keep_active = keep_active;
+        self
+    }
+}
+
+/// SPI master
+pub struct Spi<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+    clocks: Clocks,
+    peripheral_select: PeripheralSelect,
+    current: ChipSelect,
+}
+
+pub trait SpiExt<PINS>: Sized {
+    /// Configures the SPI controller as a master.  Every chip select in use
+    /// still needs a `configure_chip_select`.
+    fn spi(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Spi<Self, PINS>;
+}
+
+impl<SCK, MISO, MOSI> SpiExt<(SCK, MISO, MOSI)> for SPI0
+where
+    SCK: SckPin<SPI0>,
+    MISO: MisoPin<SPI0>,
+    MOSI: MosiPin<SPI0>,
+{
+    fn spi(self, pins: (SCK, MISO, MOSI), config: Config, pmc: &mut Pmc) -> Spi<SPI0, (SCK, MISO, MOSI)> {
+        pmc.enable_clock(PeripheralClock::Spi0);
+
+        // Disable write protection
+        self.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+        self.cr.write_with_zero(|w| w.spidis().set_bit());
+        self.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        let current = ChipSelect::Cs0;
+        self.mr.write(|w| {
+            let w = unsafe {
+                w
+                .pcs().bits(current.pcs())
+                .dlybcs().bits(config.delay_between_chip_selects)
+            };
+
+            w
+            .mstr().set_bit()
+            .ps().bit(config.peripheral_select == PeripheralSelect::Variable)
+            .pcsdec().clear_bit()
+            .modfdis().set_bit()
+        });
+
+        self.cr.write_with_zero(|w| w.spien().set_bit());
+
+        Spi {
+            spi: self,
+            pins,
+            clocks: pmc.clocks,
+            peripheral_select: config.peripheral_select,
+            current,
+        }
+    }
+}
+
+impl<PINS> Spi<SPI0, PINS> {
+    /// Writes the chip select register for `cs`
+    pub fn configure_chip_select(&mut self, cs: ChipSelect, config: &ChipSelectConfig) {
+        // SPCK = MCK / SCBR, SCBR = 0 is forbidden.  Datasheet §32.7.3.3
+        let master_clk = self.clocks.master_clk().0;
+        let frequency = config.frequency.0;
+        let scbr = master_clk.div_ceil(frequency);
+        assert!(scbr > 0 && scbr < 256);
+
+        self.spi.csr[cs.index()].write_with_zero(|w| {
+            let w = match config.mode.polarity {
+                Polarity::IdleLow => w.cpol().clear_bit(),
+                Polarity::IdleHigh => w.cpol().set_bit(),
+            };
+
+            // NCPHA is the inverse of the usual CPHA
+            let w = match config.mode.phase {
+                Phase::CaptureOnFirstTransition => w.ncpha().set_bit(),
+                Phase::CaptureOnSecondTransition => w.ncpha().clear_bit(),
+            };
+
+            let w = match config.bits {
+                8 => w.bits_()._8_bit(),
+                9 => w.bits_()._9_bit(),
+                10 => w.bits_()._10_bit(),
+                11 => w.bits_()._11_bit(),
+                12 => w.bits_()._12_bit(),
+                13 => w.bits_()._13_bit(),
+                14 => w.bits_()._14_bit(),
+                15 => w.bits_()._15_bit(),
+                _ => w.bits_()._16_bit(),
+            };
+
+            let w = w.csaat().bit(config.keep_active).csnaat().clear_bit();
+
+            unsafe {
+                w
+                .scbr().bits(scbr as u8)
+                .dlybs().bits(config.delay_before_clock)
+                .dlybct().bits(config.delay_between_transfers)
+            }
+        });
+    }
+
+    /// Picks the device the following transfers go to
+    pub fn select(&mut self, cs: ChipSelect) {
+        self.current = cs;
+
+        if self.peripheral_select == PeripheralSelect::Fixed {
+            self.spi.mr.modify(|_, w| unsafe { w.pcs().bits(cs.pcs()) });
+        }
+    }
+
+    /// The device the transfers currently go to
+    pub fn selected(&self) -> ChipSelect {
+        self.current
+    }
+
+    /// Releases a chip select held active by `keep_active` once the current
+    /// transfer is over
+    pub fn last_transfer(&mut self) {
+        self.spi.cr.write_with_zero(|w| w.lastxfer().set_bit());
+    }
+
+    /// Sends a word to `cs` in variable peripheral select mode.  With `last`
+    /// set the chip select is released after this word even if it's kept
+    /// active.
+    pub fn send_to(&mut self, cs: ChipSelect, word: u16, last: bool) -> nb::Result<(), Error> {
+        assert!(self.peripheral_select == PeripheralSelect::Variable);
+        self.current = cs;
+        self.send_word(word, last)
+    }
+
+    fn send_word(&mut self, word: u16, last: bool) -> nb::Result<(), Error> {
+        let sr = self.spi.sr.read();
+
+        if sr.modf().bit_is_set() {
+            return Err(nb::Error::Other(Error::ModeFault));
+        }
+
+        if sr.tdre().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let pcs = self.current.pcs();
+        self.spi.tdr.write_with_zero(|w| unsafe { w.td().bits(word).pcs().bits(pcs) }.lastxfer().bit(last));
+        Ok(())
+    }
+
+    fn read_word(&mut self) -> nb::Result<u16, Error> {
+        let sr = self.spi.sr.read();
+
+        if sr.ovres().bit_is_set() {
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+
+        if sr.modf().bit_is_set() {
+            return Err(nb::Error::Other(Error::ModeFault));
+        }
+
+        if sr.rdrf().bit_is_set() {
+            Ok(self.spi.rdr.read().rd().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Transmit holding and shift registers are both empty
+    pub fn is_idle(&self) -> bool {
+        self.spi.sr.read().txempty().bit_is_set()
+    }
+
+    /// Releases the SPI peripheral and pins
+    pub fn free(self) -> (SPI0, PINS) {
+        self.spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        (self.spi, self.pins)
+    }
+}
+
+impl<PINS> FullDuplex<u8> for Spi<SPI0, PINS> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        self.read_word().map(|word| word as u8)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+        self.send_word(byte as u16, false)
+    }
+}
+
+impl<PINS> FullDuplex<u16> for Spi<SPI0, PINS> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u16, Error> {
+        self.read_word()
+    }
+
+    fn send(&mut self, word: u16) -> nb::Result<(), Error> {
+        self.send_word(word, false)
+    }
+}
+
+impl<PINS> blocking::spi::transfer::Default<u8> for Spi<SPI0, PINS> {}
+impl<PINS> blocking::spi::write::Default<u8> for Spi<SPI0, PINS> {}
+impl<PINS> blocking::spi::transfer::Default<u16> for Spi<SPI0, PINS> {}
+impl<PINS> blocking::spi::write::Default<u16> for Spi<SPI0, PINS> {}
+
+// Byte wide DMAC transfers, only meaningful in fixed peripheral select mode
+unsafe impl<PINS> dma::PeripheralSource for Spi<SPI0, PINS> {
+    type Word = u8;
+    const HANDSHAKE: Handshake = Handshake::Spi0Rx;
+
+    fn source_address(&self) -> u32 {
+        &self.spi.rdr as *const _ as u32
+    }
+}
+
+unsafe impl<PINS> dma::PeripheralDestination for Spi<SPI0, PINS> {
+    type Word = u8;
+    const HANDSHAKE: Handshake = Handshake::Spi0Tx;
+
+    fn destination_address(&self) -> u32 {
+        &self.spi.tdr as *const _ as u32
+    }
+}
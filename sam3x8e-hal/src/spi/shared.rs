@@ -0,0 +1,253 @@
+/*
+ *    This file (src/spi/shared.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SPI bus shared between several devices
+//!
+//! `SharedBus` owns the master and hands out a `Device` per chip select.  Each
+//! device carries its own mode and speed and locks the bus for the length of a
+//! transaction.  The lock never spins: a device that finds the bus taken gets
+//! `Error::Busy`, which only happens when an interrupt handler preempts a
+//! transaction in progress, so devices can be used from interrupt context
+//! without deadlocking.
+//!
+//! ```ignore
+//! let bus = SharedBus::new(spi);
+//! let mut flash = bus.hardware_device(npcs0, &ChipSelectConfig::new().frequency(20.mhz()))?;
+//! let mut sd = bus.gpio_device(sd_cs, ChipSelect::Cs3, ChipSelectConfig::new().mode(MODE_0));
+//! ```
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::hal::blocking;
+use crate::hal::digital::v2::OutputPin;
+use crate::pac::SPI0;
+use crate::spi::{self, ChipSelect, ChipSelectConfig, NpcsPin, Spi};
+
+/// Shared bus errors
+#[derive(Debug)]
+pub enum Error {
+    /// Another device is in the middle of a transaction
+    Busy,
+    Spi(spi::Error),
+}
+
+/// An SPI master shared between devices
+pub struct SharedBus<PINS> {
+    spi: UnsafeCell<Spi<SPI0, PINS>>,
+    locked: AtomicBool,
+}
+
+// The master is only ever reached through the lock
+unsafe impl<PINS: Send> Sync for SharedBus<PINS> {}
+
+impl<PINS> SharedBus<PINS> {
+    pub fn new(spi: Spi<SPI0, PINS>) -> Self {
+        SharedBus {
+            spi: UnsafeCell::new(spi),
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// A device behind a hardware chip select.  Its settings go into the chip
+    /// select's own register, once, which takes the bus like a transaction.
+    pub fn hardware_device<NPCS>(&self, npcs: NPCS, config: &ChipSelectConfig) -> Result<Device<'_, PINS, Hardware<NPCS>>, Error>
+    where
+        NPCS: NpcsPin<SPI0>,
+    {
+        self.try_lock()?.configure_chip_select(NPCS::CS, config);
+        Ok(Device { bus: self, line: Hardware { npcs } })
+    }
+
+    /// A device behind a GPIO chip select.  The settings are written to
+    /// `slot`'s chip select register at the start of every transaction, so
+    /// `slot` can be shared by every GPIO device but shouldn't have its NPCS
+    /// line routed to a pin.
+    pub fn gpio_device<PIN>(&self, mut pin: PIN, slot: ChipSelect, config: ChipSelectConfig) -> Device<'_, PINS, Gpio<PIN>>
+    where
+        PIN: OutputPin,
+    {
+        let _ = pin.set_high();
+        Device { bus: self, line: Gpio { pin, slot, config } }
+    }
+
+    fn try_lock(&self) -> Result<Locked<'_, PINS>, Error> {
+        match self.locked.swap(true, Ordering::Acquire) {
+            true => Err(Error::Busy),
+            false => Ok(Locked { bus: self }),
+        }
+    }
+
+    /// Hands back the master once every device is gone
+    pub fn free(self) -> Spi<SPI0, PINS> {
+        self.spi.into_inner()
+    }
+}
+
+/// The bus lock, released on drop
+struct Locked<'a, PINS> {
+    bus: &'a SharedBus<PINS>,
+}
+
+impl<'a, PINS> Deref for Locked<'a, PINS> {
+    type Target = Spi<SPI0, PINS>;
+
+    fn deref(&self) -> &Spi<SPI0, PINS> {
+        unsafe { &*self.bus.spi.get() }
+    }
+}
+
+impl<'a, PINS> DerefMut for Locked<'a, PINS> {
+    fn deref_mut(&mut self) -> &mut Spi<SPI0, PINS> {
+        unsafe { &mut *self.bus.spi.get() }
+    }
+}
+
+impl<'a, PINS> Drop for Locked<'a, PINS> {
+    fn drop(&mut self) {
+        self.bus.locked.store(false, Ordering::Release);
+    }
+}
+
+/// How a device is selected
+pub trait Line<PINS> {
+    /// Points the master at the device and selects it
+    fn acquire(&mut self, spi: &mut Spi<SPI0, PINS>);
+
+    /// Deselects the device once the master is idle
+    fn release(&mut self, spi: &mut Spi<SPI0, PINS>);
+}
+
+/// Selected by one of the master's NPCS lines
+pub struct Hardware<NPCS> {
+    npcs: NPCS,
+}
+
+impl<PINS, NPCS: NpcsPin<SPI0>> Line<PINS> for Hardware<NPCS> {
+    fn acquire(&mut self, spi: &mut Spi<SPI0, PINS>) {
+        spi.select(NPCS::CS);
+    }
+
+    fn release(&mut self, spi: &mut Spi<SPI0, PINS>) {
+        // Lets go of a chip select held by `keep_active`
+        spi.last_transfer();
+    }
+}
+
+/// Selected by a GPIO, active low
+pub struct Gpio<PIN> {
+    pin: PIN,
+    slot: ChipSelect,
+    config: ChipSelectConfig,
+}
+
+impl<PINS, PIN: OutputPin> Line<PINS> for Gpio<PIN> {
+    fn acquire(&mut self, spi: &mut Spi<SPI0, PINS>) {
+        spi.configure_chip_select(self.slot, &self.config);
+        spi.select(self.slot);
+        let _ = self.pin.set_low();
+    }
+
+    fn release(&mut self, spi: &mut Spi<SPI0, PINS>) {
+        while !spi.is_idle() {}
+        let _ = self.pin.set_high();
+    }
+}
+
+/// A device on a shared bus
+pub struct Device<'a, PINS, LINE> {
+    bus: &'a SharedBus<PINS>,
+    line: LINE,
+}
+
+impl<'a, PINS, LINE: Line<PINS>> Device<'a, PINS, LINE> {
+    /// Locks the bus and selects the device until the guard is dropped
+    pub fn lock(&mut self) -> Result<Guard<'_, PINS, LINE>, Error> {
+        let mut spi = self.bus.try_lock()?;
+        self.line.acquire(&mut spi);
+
+        Ok(Guard { spi, line: &mut self.line })
+    }
+}
+
+impl<'a, PINS, NPCS> Device<'a, PINS, Hardware<NPCS>> {
+    /// Hands back the chip select pin
+    pub fn free(self) -> NPCS {
+        self.line.npcs
+    }
+}
+
+impl<'a, PINS, PIN> Device<'a, PINS, Gpio<PIN>> {
+    /// Hands back the chip select pin
+    pub fn free(self) -> PIN {
+        self.line.pin
+    }
+}
+
+/// Exclusive use of the bus, dereferences to the master
+pub struct Guard<'g, PINS, LINE: Line<PINS>> {
+    spi: Locked<'g, PINS>,
+    line: &'g mut LINE,
+}
+
+impl<'g, PINS, LINE: Line<PINS>> Deref for Guard<'g, PINS, LINE> {
+    type Target = Spi<SPI0, PINS>;
+
+    fn deref(&self) -> &Spi<SPI0, PINS> {
+        &self.spi
+    }
+}
+
+impl<'g, PINS, LINE: Line<PINS>> DerefMut for Guard<'g, PINS, LINE> {
+    fn deref_mut(&mut self) -> &mut Spi<SPI0, PINS> {
+        &mut self.spi
+    }
+}
+
+impl<'g, PINS, LINE: Line<PINS>> Drop for Guard<'g, PINS, LINE> {
+    fn drop(&mut self) {
+        // The lock itself is released after this, when `spi` is dropped
+        self.line.release(&mut self.spi);
+    }
+}
+
+macro_rules! device_blocking {
+    ($($W:ty),+) => {
+        $(
+            impl<'a, PINS, LINE: Line<PINS>> blocking::spi::Transfer<$W> for Device<'a, PINS, LINE> {
+                type Error = Error;
+
+                fn transfer<'w>(&mut self, words: &'w mut [$W]) -> Result<&'w [$W], Error> {
+                    let mut bus = self.lock()?;
+                    bus.transfer(words).map_err(Error::Spi)
+                }
+            }
+
+            impl<'a, PINS, LINE: Line<PINS>> blocking::spi::Write<$W> for Device<'a, PINS, LINE> {
+                type Error = Error;
+
+                fn write(&mut self, words: &[$W]) -> Result<(), Error> {
+                    let mut bus = self.lock()?;
+                    bus.write(words).map_err(Error::Spi)
+                }
+            }
+        )+
+    };
+}
+
+device_blocking!(u8, u16);
@@ -0,0 +1,480 @@
+/*
+ *    This file (src/spi/slave.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! SPI slave.  Datasheet §32.7.4
+//!
+//! The external master frames transfers with NSS (NPCS0).  Its rising edge
+//! ends a frame and is reported as `Event::FrameEnd`.  The clock mode and word
+//! size come from chip select register 0, the baud rate from the master.
+//!
+//! `buffered` moves the data through ring buffers from the SPI0 interrupt and
+//! keeps the frames apart, so the main loop deals in whole frames.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use cortex_m::peripheral::NVIC;
+
+use crate::gpio::PeripheralA;
+use crate::gpio::pioa::PA28;
+use crate::hal::spi::{self, Mode, Phase, Polarity};
+use crate::pac::{Interrupt, SPI0};
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::serial::buffered::{Consumer, Producer, RingBuffer};
+use crate::spi::{Error, MisoPin, MosiPin, SckPin};
+
+/// Slave select input
+pub trait NssPin<SPI> {}
+
+impl NssPin<SPI0> for PA28<PeripheralA> {}
+
+/// Interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// A word has been received
+    RxReady,
+    /// The transmit data register can take the next word
+    TxReady,
+    /// NSS went high
+    FrameEnd,
+    /// A word was clocked out before one was written
+    Underrun,
+    /// A word was received before the previous one was read
+    Overrun,
+}
+
+/// Slave configuration
+pub struct Config {
+    mode: Mode,
+    bits: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            mode: spi::MODE_0,
+            bits: 8,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// Must match the master's
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Bits per transfer, 8 to 16
+    pub fn bits(mut self, bits: u8) -> Self {
+        assert!((8..=16).contains(&bits));
+        self.bits = bits;
+        self
+    }
+}
+
+/// Status flags cleared by reading SR, kept until they've been reported
+#[derive(Default)]
+struct Latched {
+    overrun: bool,
+    underrun: bool,
+    frame_end: bool,
+}
+
+/// SPI slave
+pub struct SpiSlave<PINS> {
+    spi: SPI0,
+    pins: PINS,
+    latched: Latched,
+}
+
+pub trait SpiSlaveExt<PINS>: Sized {
+    /// Configures the SPI controller as a slave
+    fn spi_slave(self, pins: PINS, config: Config, pmc: &mut Pmc) -> SpiSlave<PINS>;
+}
+
+impl<SCK, MISO, MOSI, NSS> SpiSlaveExt<(SCK, MISO, MOSI, NSS)> for SPI0
+where
+    SCK: SckPin<SPI0>,
+    MISO: MisoPin<SPI0>,
+    MOSI: MosiPin<SPI0>,
+    NSS: NssPin<SPI0>,
+{
+    fn spi_slave(self, pins: (SCK, MISO, MOSI, NSS), config: Config, pmc: &mut Pmc) -> SpiSlave<(SCK, MISO, MOSI, NSS)> {
+        pmc.enable_clock(PeripheralClock::Spi0);
+
+        // Disable write protection
+        self.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+        self.cr.write_with_zero(|w| w.spidis().set_bit());
+        self.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        self.mr.write(|w| w.mstr().clear_bit());
+
+        // Only the mode and word size apply in slave mode
+        self.csr[0].write_with_zero(|w| {
+            let w = match config.mode.polarity {
+                Polarity::IdleLow => w.cpol().clear_bit(),
+                Polarity::IdleHigh => w.cpol().set_bit(),
+            };
+
+            let w = match config.mode.phase {
+                Phase::CaptureOnFirstTransition => w.ncpha().set_bit(),
+                Phase::CaptureOnSecondTransition => w.ncpha().clear_bit(),
+            };
+
+            match config.bits {
+                8 => w.bits_()._8_bit(),
+                9 => w.bits_()._9_bit(),
+                10 => w.bits_()._10_bit(),
+                11 => w.bits_()._11_bit(),
+                12 => w.bits_()._12_bit(),
+                13 => w.bits_()._13_bit(),
+                14 => w.bits_()._14_bit(),
+                15 => w.bits_()._15_bit(),
+                _ => w.bits_()._16_bit(),
+            }
+        });
+
+        self.cr.write_with_zero(|w| w.spien().set_bit());
+
+        SpiSlave { spi: self, pins, latched: Latched::default() }
+    }
+}
+
+impl<PINS> SpiSlave<PINS> {
+    /// Reads SR, latching the flags the read clears
+    fn status(&mut self) -> crate::pac::spi0::sr::R {
+        let sr = self.spi.sr.read();
+        self.latched.overrun |= sr.ovres().bit_is_set();
+        self.latched.underrun |= sr.undes().bit_is_set();
+        self.latched.frame_end |= sr.nssr().bit_is_set();
+        sr
+    }
+
+    /// Reads a received word.  An overrun is reported once, the word that
+    /// caused it is still there to be read.
+    pub fn read(&mut self) -> nb::Result<u16, Error> {
+        let sr = self.status();
+
+        if self.latched.overrun {
+            self.latched.overrun = false;
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+
+        if sr.rdrf().bit_is_set() {
+            Ok(self.spi.rdr.read().rd().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Queues the word sent during the master's next transfer.  An underrun is
+    /// reported once.
+    pub fn write(&mut self, word: u16) -> nb::Result<(), Error> {
+        let sr = self.status();
+
+        if self.latched.underrun {
+            self.latched.underrun = false;
+            return Err(nb::Error::Other(Error::Underrun));
+        }
+
+        if sr.tdre().bit_is_set() {
+            self.spi.tdr.write_with_zero(|w| unsafe { w.td().bits(word) });
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Returns and clears whether NSS has gone high since the last call
+    pub fn frame_ended(&mut self) -> bool {
+        self.status();
+        core::mem::replace(&mut self.latched.frame_end, false)
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::RxReady => self.spi.ier.write_with_zero(|w| w.rdrf().set_bit()),
+            Event::TxReady => self.spi.ier.write_with_zero(|w| w.tdre().set_bit()),
+            Event::FrameEnd => self.spi.ier.write_with_zero(|w| w.nssr().set_bit()),
+            Event::Underrun => self.spi.ier.write_with_zero(|w| w.undes().set_bit()),
+            Event::Overrun => self.spi.ier.write_with_zero(|w| w.ovres().set_bit()),
+        }
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::RxReady => self.spi.idr.write_with_zero(|w| w.rdrf().set_bit()),
+            Event::TxReady => self.spi.idr.write_with_zero(|w| w.tdre().set_bit()),
+            Event::FrameEnd => self.spi.idr.write_with_zero(|w| w.nssr().set_bit()),
+            Event::Underrun => self.spi.idr.write_with_zero(|w| w.undes().set_bit()),
+            Event::Overrun => self.spi.idr.write_with_zero(|w| w.ovres().set_bit()),
+        }
+    }
+
+    /// Releases the SPI peripheral and pins
+    pub fn free(self) -> (SPI0, PINS) {
+        self.spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        (self.spi, self.pins)
+    }
+}
+
+/// Number of completed frames that can wait to be read
+pub const MAX_FRAMES: usize = 8;
+
+const NO_ERROR: u8 = 0;
+const OVERRUN: u8 = 1;
+const UNDERRUN: u8 = 2;
+
+/// Frame boundaries and errors passed from the interrupt handler to the
+/// `Reader`.  Lives in a static next to the ring buffers.
+pub struct Frames {
+    lengths: [AtomicUsize; MAX_FRAMES + 1],
+    /// Bytes to drop ahead of each frame, left by frames that couldn't be
+    /// queued
+    skips: [AtomicUsize; MAX_FRAMES + 1],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    error: AtomicU8,
+}
+
+impl Frames {
+    pub const fn new() -> Self {
+        Frames {
+            lengths: [
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+            ],
+            skips: [
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+                AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+            ],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            error: AtomicU8::new(NO_ERROR),
+        }
+    }
+
+    /// Only called by the handler
+    fn push(&self, skip: usize, length: usize) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let next = (head + 1) % (MAX_FRAMES + 1);
+
+        if next == self.tail.load(Ordering::Acquire) {
+            return false;
+        }
+
+        self.skips[head].store(skip, Ordering::Relaxed);
+        self.lengths[head].store(length, Ordering::Relaxed);
+        self.head.store(next, Ordering::Release);
+        true
+    }
+
+    /// Only called by the reader, returns the bytes to skip and the frame
+    /// length
+    fn pop(&self) -> Option<(usize, usize)> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let skip = self.skips[tail].load(Ordering::Relaxed);
+        let length = self.lengths[tail].load(Ordering::Relaxed);
+        self.tail.store((tail + 1) % (MAX_FRAMES + 1), Ordering::Release);
+        Some((skip, length))
+    }
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Main loop side of the receive buffer
+pub struct Reader<const RX: usize> {
+    rx: Consumer<RX>,
+    frames: &'static Frames,
+}
+
+/// Main loop side of the transmit buffer
+pub struct Writer<const TX: usize> {
+    tx: Producer<TX>,
+}
+
+/// Interrupt side of the buffers, owns the slave
+pub struct Handler<PINS, const RX: usize, const TX: usize> {
+    slave: SpiSlave<PINS>,
+    rx: Producer<RX>,
+    tx: Consumer<TX>,
+    frames: &'static Frames,
+    /// Bytes received in the frame in progress
+    length: usize,
+    /// Bytes of frames that couldn't be queued, still in the receive buffer
+    dropped: usize,
+}
+
+/// Splits an 8 bit slave into ring buffered halves.  The SPI0 interrupt still
+/// has to be unmasked in the NVIC.  Panics if the slave transfers more than
+/// 8 bits at a time.
+pub fn buffered<PINS, const RX: usize, const TX: usize>(
+    mut slave: SpiSlave<PINS>,
+    rx: &'static RingBuffer<RX>,
+    tx: &'static RingBuffer<TX>,
+    frames: &'static Frames,
+) -> (Reader<RX>, Writer<TX>, Handler<PINS, RX, TX>) {
+    // The ring buffers hold bytes, wider words would be truncated
+    assert!(slave.spi.csr[0].read().bits_().is_8_bit(), "buffering needs 8 bit transfers");
+
+    let (rx_producer, rx_consumer) = rx.split();
+    let (tx_producer, tx_consumer) = tx.split();
+
+    slave.listen(Event::RxReady);
+    slave.listen(Event::FrameEnd);
+    slave.listen(Event::Overrun);
+    slave.listen(Event::Underrun);
+
+    (
+        Reader { rx: rx_consumer, frames },
+        Writer { tx: tx_producer },
+        Handler { slave, rx: rx_producer, tx: tx_consumer, frames, length: 0, dropped: 0 },
+    )
+}
+
+impl<PINS, const RX: usize, const TX: usize> Handler<PINS, RX, TX> {
+    /// Moves data between the SPI and the ring buffers.  Call this from the
+    /// SPI0 interrupt handler.
+    pub fn on_interrupt(&mut self) {
+        let sr = self.slave.status();
+
+        if sr.rdrf().bit_is_set() {
+            let byte = self.slave.spi.rdr.read().rd().bits() as u8;
+
+            match self.rx.push(byte) {
+                Ok(()) => self.length += 1,
+                // The main loop isn't keeping up
+                Err(_) => self.frames.error.store(OVERRUN, Ordering::Release),
+            }
+        }
+
+        if sr.tdre().bit_is_set() {
+            if let Some(byte) = self.tx.pop() {
+                self.slave.spi.tdr.write_with_zero(|w| unsafe { w.td().bits(byte as u16) });
+            }
+        }
+
+        // TDRE stays set while the data register is empty, only listen for it
+        // while there's something to send
+        match self.tx.is_empty() {
+            true => self.slave.unlisten(Event::TxReady),
+            false => self.slave.listen(Event::TxReady),
+        }
+
+        let latched = &mut self.slave.latched;
+
+        if core::mem::replace(&mut latched.overrun, false) {
+            self.frames.error.store(OVERRUN, Ordering::Release);
+        }
+
+        if core::mem::replace(&mut latched.underrun, false) {
+            self.frames.error.store(UNDERRUN, Ordering::Release);
+        }
+
+        if core::mem::replace(&mut latched.frame_end, false) {
+            // A frame that can't be queued is skipped by the reader ahead of
+            // the next one that can, so the frames after it stay aligned
+            match self.frames.push(self.dropped, self.length) {
+                true => self.dropped = 0,
+                false => {
+                    self.dropped += self.length;
+                    self.frames.error.store(OVERRUN, Ordering::Release);
+                }
+            }
+
+            self.length = 0;
+        }
+    }
+
+    /// Releases the slave.  The ring buffers stay split.
+    pub fn free(mut self) -> SpiSlave<PINS> {
+        self.slave.unlisten(Event::RxReady);
+        self.slave.unlisten(Event::TxReady);
+        self.slave.unlisten(Event::FrameEnd);
+        self.slave.unlisten(Event::Overrun);
+        self.slave.unlisten(Event::Underrun);
+        self.slave
+    }
+}
+
+impl<const RX: usize> Reader<RX> {
+    /// Reads the oldest complete frame into `buffer`, returning its length.
+    /// Whatever doesn't fit in `buffer` is dropped.  Errors are reported once,
+    /// ahead of the frames received after them.
+    pub fn read_frame(&mut self, buffer: &mut [u8]) -> nb::Result<usize, Error> {
+        match self.frames.error.swap(NO_ERROR, Ordering::AcqRel) {
+            OVERRUN => return Err(nb::Error::Other(Error::Overrun)),
+            UNDERRUN => return Err(nb::Error::Other(Error::Underrun)),
+            _ => {},
+        }
+
+        let (skip, length) = self.frames.pop().ok_or(nb::Error::WouldBlock)?;
+
+        for _ in 0..skip {
+            self.rx.pop();
+        }
+
+        let mut stored = 0;
+        for _ in 0..length {
+            if let Some(byte) = self.rx.pop() {
+                if stored < buffer.len() {
+                    buffer[stored] = byte;
+                    stored += 1;
+                }
+            }
+        }
+
+        Ok(stored)
+    }
+}
+
+impl<const TX: usize> Writer<TX> {
+    /// Queues `bytes` to be clocked out by the master.  Either all of them go
+    /// in or none do.  Bytes the master doesn't clock out carry over to the
+    /// next frame.
+    pub fn write(&mut self, bytes: &[u8]) -> nb::Result<(), Error> {
+        if self.tx.free() < bytes.len() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        for &byte in bytes {
+            let _ = self.tx.push(byte);
+        }
+
+        // Run the handler so it starts listening for TDRE
+        NVIC::pend(Interrupt::SPI0);
+        Ok(())
+    }
+}
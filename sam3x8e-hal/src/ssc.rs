@@ -0,0 +1,678 @@
+/*
+ *    This file (src/ssc.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Synchronous Serial Controller
+//!
+//! The SSC streams fixed size words framed by a frame sync signal, which
+//! covers I²S, left justified and TDM audio links.  The transmitter uses TK,
+//! TF and TD, the receiver RK, RF and RD.  When the receiver has no pins of its
+//! own it runs off the transmitter's clock and frame sync, the usual wiring
+//! for a codec.
+//!
+//! As master the SSC drives the bit clock from the master clock divider and
+//! generates the frame sync, as slave both come in on the pins.  The SSC has
+//! no PDC channel, `split` the driver and hand the halves to DMAC channels to
+//! stream samples.
+//!
+//! Instead of a frame sync the receiver can also wait for a `SyncWord` in the
+//! data stream itself, for raw synchronous protocols that mark frames with a
+//! sync pattern.
+//!
+//! ```ignore
+//! let config = ssc::Config::new()
+//!     .format(Format::I2s)
+//!     .role(Role::Master { sample_rate: 48_000.hz() })
+//!     .word_length(16);
+//! let ssc = dp.SSC.ssc::<u16>(((tk, tf, td), rd), config, &mut pmc);
+//! let (mut tx, mut rx) = ssc.split();
+//! ```
+
+use crate::dma::{self, Handshake};
+use crate::gpio::{PeripheralA, PeripheralB};
+use crate::gpio::pioa::{PA14, PA15, PA16};
+use crate::gpio::piob::{PB17, PB18, PB19};
+use crate::latch::Latch;
+use crate::pac::SSC;
+use crate::pdc::Word;
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::time::Hertz;
+
+/// SSC errors
+#[derive(Debug)]
+pub enum Error {
+    /// A word was received before the previous one was read
+    Overrun,
+}
+
+/// Frame layout
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    /// Two channels, frame sync low for the left one, data one clock after
+    /// the frame sync edge
+    I2s,
+    /// Two channels, frame sync high for the left one, data aligned with the
+    /// frame sync edge
+    LeftJustified,
+    /// `slots` back to back words after a one clock frame sync pulse.  `delay`
+    /// is the number of clocks from the pulse to the first word, 1 for DSP
+    /// mode A, 0 for mode B.
+    Tdm { slots: u8, delay: u8 },
+}
+
+/// Who drives the bit clock and frame sync
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    /// Generated from the master clock, `sample_rate` frames per second
+    Master { sample_rate: Hertz },
+    /// Driven by the other end on the clock and frame sync pins
+    Slave,
+}
+
+/// Interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    TxReady,
+    /// The last word has been shifted out
+    TxEmpty,
+    /// A transmit frame sync has been seen
+    TxSync,
+    RxReady,
+    Overrun,
+    /// A receive frame sync has been seen
+    RxSync,
+    /// The sync word has been received
+    Compare0,
+    /// The end pattern has been received
+    Compare1,
+}
+
+pub trait TkPin<SSC> {}
+pub trait TfPin<SSC> {}
+pub trait TdPin<SSC> {}
+pub trait RkPin<SSC> {}
+pub trait RfPin<SSC> {}
+pub trait RdPin<SSC> {}
+
+impl TkPin<SSC> for PA14<PeripheralB> {}
+impl TfPin<SSC> for PA15<PeripheralB> {}
+impl TdPin<SSC> for PA16<PeripheralB> {}
+impl RfPin<SSC> for PB17<PeripheralA> {}
+impl RdPin<SSC> for PB18<PeripheralA> {}
+impl RkPin<SSC> for PB19<PeripheralA> {}
+
+/// Transmitter pins, `(TK, TF, TD)` or `()` when the transmitter isn't used
+pub trait TxPins<SSC> {
+    const USED: bool;
+}
+
+/// Receiver pins: `(RK, RF, RD)`, `(RK, RD)` when frames start on a
+/// `SyncWord`, `RD` alone to run off the transmitter's clock and frame sync,
+/// or `()` when the receiver isn't used
+pub trait RxPins<SSC> {
+    const USED: bool;
+    /// Clocked and framed by RK / RF rather than by the transmitter
+    const OWN_CLOCKS: bool;
+}
+
+impl TxPins<SSC> for () {
+    const USED: bool = false;
+}
+
+impl<TK, TF, TD> TxPins<SSC> for (TK, TF, TD)
+where
+    TK: TkPin<SSC>,
+    TF: TfPin<SSC>,
+    TD: TdPin<SSC>,
+{
+    const USED: bool = true;
+}
+
+impl RxPins<SSC> for () {
+    const USED: bool = false;
+    const OWN_CLOCKS: bool = false;
+}
+
+impl<RD: RdPin<SSC>> RxPins<SSC> for RD {
+    const USED: bool = true;
+    const OWN_CLOCKS: bool = false;
+}
+
+/// Own clock, frames started by a `SyncWord`
+impl<RK, RD> RxPins<SSC> for (RK, RD)
+where
+    RK: RkPin<SSC>,
+    RD: RdPin<SSC>,
+{
+    const USED: bool = true;
+    const OWN_CLOCKS: bool = true;
+}
+
+impl<RK, RF, RD> RxPins<SSC> for (RK, RF, RD)
+where
+    RK: RkPin<SSC>,
+    RF: RfPin<SSC>,
+    RD: RdPin<SSC>,
+{
+    const USED: bool = true;
+    const OWN_CLOCKS: bool = true;
+}
+
+/// Word types the holding registers are accessed with
+pub trait Sample: Word {
+    const BITS: u8;
+
+    fn from_bits(bits: u32) -> Self;
+    fn into_bits(self) -> u32;
+}
+
+impl Sample for u8 {
+    const BITS: u8 = 8;
+
+    fn from_bits(bits: u32) -> Self { bits as u8 }
+    fn into_bits(self) -> u32 { self as u32 }
+}
+
+impl Sample for u16 {
+    const BITS: u8 = 16;
+
+    fn from_bits(bits: u32) -> Self { bits as u16 }
+    fn into_bits(self) -> u32 { self as u32 }
+}
+
+impl Sample for u32 {
+    const BITS: u8 = 32;
+
+    fn from_bits(bits: u32) -> Self { bits }
+    fn into_bits(self) -> u32 { self }
+}
+
+/// Pattern the receiver waits for in the data stream instead of a frame sync
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SyncWord {
+    pattern: u16,
+    bits: u8,
+    end: Option<u16>,
+}
+
+impl SyncWord {
+    /// The low `bits` bits of `pattern`, 1 to 16, compared against the last
+    /// bits received.  After a match the receiver takes in one frame and then
+    /// waits for the pattern again.
+    pub fn new(pattern: u16, bits: u8) -> Self {
+        assert!((1..=16).contains(&bits));
+        SyncWord { pattern, bits, end: None }
+    }
+
+    /// Keeps receiving frames after a match until `pattern`, of the same
+    /// length, shows up
+    pub fn until(mut self, pattern: u16) -> Self {
+        self.end = Some(pattern);
+        self
+    }
+}
+
+/// SSC configuration, applied to both the transmitter and the receiver
+pub struct Config {
+    format: Format,
+    role: Role,
+    word_length: u8,
+    sync_word: Option<SyncWord>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            format: Format::I2s,
+            role: Role::Slave,
+            word_length: 16,
+            sync_word: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        if let Format::Tdm { slots, .. } = format {
+            assert!((1..=16).contains(&slots));
+        }
+        self.format = format;
+        self
+    }
+
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Bits per word and per slot, 2 to 32, sent MSB first
+    pub fn word_length(mut self, bits: u8) -> Self {
+        assert!((2..=32).contains(&bits));
+        self.word_length = bits;
+        self
+    }
+
+    /// Starts receive frames on a sync word rather than on the frame sync.
+    /// The frame is laid out by the format, `Format::Tdm` giving the words
+    /// per frame and the delay from the end of the sync word to the first one.
+    pub fn sync_word(mut self, sync_word: SyncWord) -> Self {
+        self.sync_word = Some(sync_word);
+        self
+    }
+}
+
+/// How a frame starts
+#[derive(Clone, Copy)]
+enum Start {
+    Falling,
+    Rising,
+}
+
+/// Register level view of a `Config`, shared by both directions
+struct Timing {
+    master: bool,
+    start: Start,
+    /// STTDLY: clocks from the start to the first data bit
+    delay: u8,
+    /// PERIOD: half the frame length minus one, master only
+    period: u8,
+    /// DATLEN: bits per word minus one
+    datlen: u8,
+    /// DATNB: words per frame minus one
+    datnb: u8,
+    /// FSLEN / FSLEN_EXT: frame sync clocks minus one, master only
+    fslen: u8,
+    /// Frame sync pulse is active low
+    negative: bool,
+}
+
+impl Timing {
+    fn new(config: &Config) -> Timing {
+        let bits = config.word_length;
+        let master = match config.role {
+            Role::Master { .. } => true,
+            Role::Slave => false,
+        };
+
+        let (start, delay, words, fslen, negative) = match config.format {
+            // The left channel starts on the falling / rising edge of the
+            // frame sync, the right one follows straight after
+            Format::I2s => (Start::Falling, 1, 2, bits - 1, true),
+            Format::LeftJustified => (Start::Rising, 0, 2, bits - 1, false),
+            Format::Tdm { slots, delay } => (Start::Rising, delay, slots, 0, false),
+        };
+
+        let frame_bits = words as u32 * bits as u32;
+        assert!(!master || (frame_bits.is_multiple_of(2) && frame_bits <= 512));
+
+        Timing {
+            master,
+            start,
+            delay,
+            period: if master { (frame_bits / 2 - 1) as u8 } else { 0 },
+            datlen: bits - 1,
+            datnb: words - 1,
+            fslen,
+            negative,
+        }
+    }
+
+    fn frame_bits(&self) -> u32 {
+        (self.datnb as u32 + 1) * (self.datlen as u32 + 1)
+    }
+}
+
+fn configure_transmitter(ssc: &SSC, timing: &Timing) {
+    ssc.tcmr.write(|w| {
+        let w = match timing.master {
+            true => w.cks().mck().cko().continuous(),
+            false => w.cks().tk().cko().none(),
+        };
+        let w = match timing.start {
+            Start::Falling => w.start().tf_falling(),
+            Start::Rising => w.start().tf_rising(),
+        };
+
+        // Data changes on the falling edge of the clock
+        unsafe { w.cki().clear_bit().ckg().continuous().sttdly().bits(timing.delay).period().bits(timing.period) }
+    });
+
+    ssc.tfmr.write(|w| {
+        let w = match (timing.master, timing.negative) {
+            (false, _) => w.fsos().none(),
+            (true, true) => w.fsos().negative(),
+            (true, false) => w.fsos().positive(),
+        };
+
+        unsafe {
+            w.datlen().bits(timing.datlen)
+                .msbf().set_bit()
+                .datnb().bits(timing.datnb)
+                .fslen().bits(timing.fslen & 0xf)
+                .fslen_ext().bits(timing.fslen >> 4)
+                .fsedge().positive()
+        }
+    });
+}
+
+/// Receive compare registers and frame mode for a sync word.  The compared
+/// length is taken from FSLEN.
+fn configure_sync_word(ssc: &SSC, timing: &Timing, sync_word: &SyncWord) {
+    ssc.rcmr.modify(|_, w| w.start().cmp_0().stop().bit(sync_word.end.is_some()));
+
+    ssc.rc0r.write(|w| unsafe { w.cp0().bits(sync_word.pattern) });
+    if let Some(end) = sync_word.end {
+        ssc.rc1r.write(|w| unsafe { w.cp1().bits(end) });
+    }
+
+    let fslen = sync_word.bits - 1;
+    ssc.rfmr.write(|w| unsafe {
+        w.datlen().bits(timing.datlen)
+            .msbf().set_bit()
+            .datnb().bits(timing.datnb)
+            .fslen().bits(fslen & 0xf)
+            .fslen_ext().bits(fslen >> 4)
+            .fsos().none()
+    });
+}
+
+fn configure_receiver(ssc: &SSC, timing: &Timing, own_clocks: bool) {
+    ssc.rcmr.write(|w| {
+        let w = match (own_clocks, timing.master) {
+            (false, _) => w.cks().tk().cko().none().start().transmit(),
+            (true, true) => w.cks().mck().cko().continuous(),
+            (true, false) => w.cks().rk().cko().none(),
+        };
+        let w = match (own_clocks, timing.start) {
+            (false, _) => w,
+            (true, Start::Falling) => w.start().rf_falling(),
+            (true, Start::Rising) => w.start().rf_rising(),
+        };
+        let period = if own_clocks { timing.period } else { 0 };
+
+        // Data is sampled on the rising edge of the clock
+        unsafe { w.cki().set_bit().ckg().continuous().sttdly().bits(timing.delay).period().bits(period) }
+    });
+
+    ssc.rfmr.write(|w| {
+        let w = match (own_clocks && timing.master, timing.negative) {
+            (false, _) => w.fsos().none(),
+            (true, true) => w.fsos().negative(),
+            (true, false) => w.fsos().positive(),
+        };
+
+        unsafe {
+            w.datlen().bits(timing.datlen)
+                .msbf().set_bit()
+                .datnb().bits(timing.datnb)
+                .fslen().bits(timing.fslen & 0xf)
+                .fslen_ext().bits(timing.fslen >> 4)
+                .fsedge().positive()
+        }
+    });
+}
+
+/// SSC driver, holding words of type `W`
+pub struct Ssc<PINS, W> {
+    ssc: SSC,
+    pins: PINS,
+    _word: core::marker::PhantomData<W>,
+}
+
+/// Transmitting half of a split SSC, holding on to the SSC and its pins
+/// until the halves are joined again
+pub struct Transmitter<PINS, W> {
+    ssc: SSC,
+    pins: PINS,
+    _word: core::marker::PhantomData<W>,
+}
+
+/// Receiving half of a split SSC
+pub struct Receiver<W> {
+    _word: core::marker::PhantomData<W>,
+}
+
+pub trait SscExt<PINS>: Sized {
+    /// Configures the SSC for `config` on `(transmitter pins, receiver pins)`.
+    /// `W` has to be wide enough for the word length.  Both directions are
+    /// left disabled.
+    fn ssc<W: Sample>(self, pins: PINS, config: Config, pmc: &mut Pmc) -> Ssc<PINS, W>;
+}
+
+impl<TX, RX> SscExt<(TX, RX)> for SSC
+where
+    TX: TxPins<SSC>,
+    RX: RxPins<SSC>,
+{
+    fn ssc<W: Sample>(self, pins: (TX, RX), config: Config, pmc: &mut Pmc) -> Ssc<(TX, RX), W> {
+        assert!(config.word_length <= W::BITS);
+        pmc.enable_clock(PeripheralClock::Ssc);
+
+        self.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        let timing = Timing::new(&config);
+
+        // The divided clock runs at MCK / (2 * DIV)
+        if let Role::Master { sample_rate } = config.role {
+            let bit_clock = sample_rate.0 * timing.frame_bits();
+            let div = (pmc.clocks.master_clk().0 + bit_clock) / (2 * bit_clock);
+            assert!((1..=0xfff).contains(&div));
+            self.cmr.write(|w| unsafe { w.div().bits(div as u16) });
+        }
+
+        if TX::USED {
+            configure_transmitter(&self, &timing);
+        }
+        if RX::USED {
+            assert!(TX::USED || RX::OWN_CLOCKS);
+            configure_receiver(&self, &timing, RX::OWN_CLOCKS);
+
+            if let Some(sync_word) = &config.sync_word {
+                configure_sync_word(&self, &timing, sync_word);
+            }
+        }
+
+        Ssc { ssc: self, pins, _word: core::marker::PhantomData }
+    }
+}
+
+impl<PINS, W> Ssc<PINS, W> {
+    /// Splits the driver into its transmitter and receiver
+    pub fn split(self) -> (Transmitter<PINS, W>, Receiver<W>) {
+        (
+            Transmitter { ssc: self.ssc, pins: self.pins, _word: core::marker::PhantomData },
+            Receiver { _word: core::marker::PhantomData },
+        )
+    }
+
+    /// Rebuilds the driver from its two halves
+    pub fn join(tx: Transmitter<PINS, W>, _rx: Receiver<W>) -> Self {
+        Ssc { ssc: tx.ssc, pins: tx.pins, _word: core::marker::PhantomData }
+    }
+
+    /// Disables both directions and releases the SSC peripheral and pins
+    pub fn free(self) -> (SSC, PINS) {
+        self.ssc.cr.write_with_zero(|w| w.txdis().set_bit().rxdis().set_bit());
+        (self.ssc, self.pins)
+    }
+}
+
+const SR_TXRDY: u32 = 1 << 0;
+const SR_TXEMPTY: u32 = 1 << 1;
+const SR_RXRDY: u32 = 1 << 4;
+const SR_OVRUN: u32 = 1 << 5;
+const SR_CP0: u32 = 1 << 8;
+const SR_CP1: u32 = 1 << 9;
+const SR_TXSYN: u32 = 1 << 10;
+const SR_RXSYN: u32 = 1 << 11;
+
+/// SR flags cleared by reading SR, kept until the half they belong to takes
+/// them so one half's reads don't swallow the other's flags
+static STATUS: Latch = Latch::new(SR_OVRUN | SR_CP0 | SR_CP1 | SR_TXSYN | SR_RXSYN);
+
+fn status() -> u32 {
+    STATUS.update(unsafe { (*SSC::ptr()).sr.read().bits() })
+}
+
+fn take_status(mask: u32) -> u32 {
+    STATUS.take(unsafe { (*SSC::ptr()).sr.read().bits() }, mask)
+}
+
+fn listen(event: Event) {
+    let ssc = unsafe { &*SSC::ptr() };
+
+    match event {
+        Event::TxReady => ssc.ier.write_with_zero(|w| w.txrdy().set_bit()),
+        Event::TxEmpty => ssc.ier.write_with_zero(|w| w.txempty().set_bit()),
+        Event::TxSync => ssc.ier.write_with_zero(|w| w.txsyn().set_bit()),
+        Event::RxReady => ssc.ier.write_with_zero(|w| w.rxrdy().set_bit()),
+        Event::Overrun => ssc.ier.write_with_zero(|w| w.ovrun().set_bit()),
+        Event::RxSync => ssc.ier.write_with_zero(|w| w.rxsyn().set_bit()),
+        Event::Compare0 => ssc.ier.write_with_zero(|w| w.cp0().set_bit()),
+        Event::Compare1 => ssc.ier.write_with_zero(|w| w.cp1().set_bit()),
+    }
+}
+
+fn unlisten(event: Event) {
+    let ssc = unsafe { &*SSC::ptr() };
+
+    match event {
+        Event::TxReady => ssc.idr.write_with_zero(|w| w.txrdy().set_bit()),
+        Event::TxEmpty => ssc.idr.write_with_zero(|w| w.txempty().set_bit()),
+        Event::TxSync => ssc.idr.write_with_zero(|w| w.txsyn().set_bit()),
+        Event::RxReady => ssc.idr.write_with_zero(|w| w.rxrdy().set_bit()),
+        Event::Overrun => ssc.idr.write_with_zero(|w| w.ovrun().set_bit()),
+        Event::RxSync => ssc.idr.write_with_zero(|w| w.rxsyn().set_bit()),
+        Event::Compare0 => ssc.idr.write_with_zero(|w| w.cp0().set_bit()),
+        Event::Compare1 => ssc.idr.write_with_zero(|w| w.cp1().set_bit()),
+    }
+}
+
+// The halves only touch their own bits of the write-only control and
+// interrupt registers, and SR goes through the latch
+impl<PINS, W: Sample> Transmitter<PINS, W> {
+    /// Starts transmitting at the next frame start
+    pub fn enable(&mut self) {
+        unsafe { (*SSC::ptr()).cr.write_with_zero(|w| w.txen().set_bit()) };
+    }
+
+    /// Stops once the current word has been sent
+    pub fn disable(&mut self) {
+        unsafe { (*SSC::ptr()).cr.write_with_zero(|w| w.txdis().set_bit()) };
+    }
+
+    /// Queues a word.  The transmitter sends zeroes while it's starved.
+    pub fn write(&mut self, word: W) -> nb::Result<(), void::Void> {
+        if status() & SR_TXRDY == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let ssc = unsafe { &*SSC::ptr() };
+        ssc.thr.write_with_zero(|w| unsafe { w.tdat().bits(word.into_bits()) });
+        Ok(())
+    }
+
+    /// Whether the last word has been shifted out
+    pub fn is_empty(&self) -> bool {
+        status() & SR_TXEMPTY != 0
+    }
+
+    /// Whether a transmit frame sync has been seen since the last call
+    pub fn take_frame_sync(&mut self) -> bool {
+        take_status(SR_TXSYN) != 0
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        listen(event);
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        unlisten(event);
+    }
+}
+
+impl<W: Sample> Receiver<W> {
+    /// Starts receiving at the next frame start
+    pub fn enable(&mut self) {
+        unsafe { (*SSC::ptr()).cr.write_with_zero(|w| w.rxen().set_bit()) };
+    }
+
+    pub fn disable(&mut self) {
+        unsafe { (*SSC::ptr()).cr.write_with_zero(|w| w.rxdis().set_bit()) };
+    }
+
+    /// Reads a word.  An overrun is reported once, the word received last is
+    /// still there to be read.
+    pub fn read(&mut self) -> nb::Result<W, Error> {
+        let ssc = unsafe { &*SSC::ptr() };
+        if take_status(SR_OVRUN) != 0 {
+            Err(nb::Error::Other(Error::Overrun))
+        } else if status() & SR_RXRDY != 0 {
+            Ok(W::from_bits(ssc.rhr.read().rdat().bits()))
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Whether a receive frame sync has been seen since the last call
+    pub fn take_frame_sync(&mut self) -> bool {
+        take_status(SR_RXSYN) != 0
+    }
+
+    /// Whether the sync word has been received since the last call
+    pub fn take_sync_word(&mut self) -> bool {
+        take_status(SR_CP0) != 0
+    }
+
+    /// Whether the end pattern has been received since the last call
+    pub fn take_end_pattern(&mut self) -> bool {
+        take_status(SR_CP1) != 0
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        listen(event);
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        unlisten(event);
+    }
+}
+
+unsafe impl<W: Sample> dma::PeripheralSource for Receiver<W> {
+    type Word = W;
+    const HANDSHAKE: Handshake = Handshake::SscRx;
+
+    fn source_address(&self) -> u32 {
+        unsafe { &(*SSC::ptr()).rhr as *const _ as u32 }
+    }
+}
+
+unsafe impl<PINS, W: Sample> dma::PeripheralDestination for Transmitter<PINS, W> {
+    type Word = W;
+    const HANDSHAKE: Handshake = Handshake::SscTx;
+
+    fn destination_address(&self) -> u32 {
+        unsafe { &(*SSC::ptr()).thr as *const _ as u32 }
+    }
+}
@@ -20,8 +20,13 @@
 use crate::time::Hertz;
 
 mod syst;
+mod tc;
+pub mod capture;
+pub mod qdec;
+pub mod waveform;
 
 pub use syst::*;
+pub use tc::*;
 
 pub trait TimerExt<TIM> {
     fn timer<T>(self, timeout: T) -> Timer<TIM>
@@ -32,4 +37,6 @@ pub trait TimerExt<TIM> {
 /// Hardware timers
 pub struct Timer<TIM> {
     tim: TIM,
+    /// Clock the timer counts from
+    clock: Hertz,
 }
@@ -0,0 +1,335 @@
+/*
+ *    This file (src/timer/capture.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! TC capture mode
+//!
+//! The counter runs from MCK / 2 and edges on TIOA load its value into RA and
+//! RB, RB only once RA has been loaded.  An external trigger edge on TIOA or
+//! TIOB restarts the count.  With the `measure` config, the falling edge
+//! restarts the count, RA holds the low time and RB the period:
+//!
+//! ```ignore
+//! let mut tacho = Capture::new(tc6, (pc25, ()), &capture::Config::measure(), &mut pmc);
+//! let measurement = block!(tacho.measurement())?;
+//! let rpm = measurement.frequency().0 * 60 / 2;
+//! ```
+//!
+//! At MCK / 2 the 32-bit counter wraps after about 100 s at 84 MHz, which
+//! bounds the longest period that can be measured.
+
+use crate::delay::{
+    TimerCounter0, TimerCounter1, TimerCounter2, TimerCounter3, TimerCounter4, TimerCounter5, TimerCounter6,
+    TimerCounter7, TimerCounter8,
+};
+use crate::latch::Latch;
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::time::{Hertz, MicroSeconds};
+
+use super::waveform::{Edge, TioaPin, TiobPin};
+
+/// Capture errors
+#[derive(Debug)]
+pub enum Error {
+    /// RA or RB was loaded again before being read, captures were lost
+    Overrun,
+}
+
+/// TIO line the external trigger is taken from.  ABETRG
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TriggerInput {
+    Tioa,
+    Tiob,
+}
+
+/// Capture configuration
+pub struct Config {
+    load_a: Option<Edge>,
+    load_b: Option<Edge>,
+    trigger: Option<(Edge, TriggerInput)>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            load_a: Some(Edge::Rising),
+            load_b: None,
+            trigger: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    /// Restarts the count on falling edges, loads RA on rising edges and RB
+    /// on falling ones, for `Capture::measurement`
+    pub fn measure() -> Self {
+        Config {
+            load_a: Some(Edge::Rising),
+            load_b: Some(Edge::Falling),
+            trigger: Some((Edge::Falling, TriggerInput::Tioa)),
+        }
+    }
+
+    /// LDRA: TIOA edge loading RA
+    pub fn load_a(mut self, edge: Option<Edge>) -> Self {
+        self.load_a = edge;
+        self
+    }
+
+    /// LDRB: TIOA edge loading RB, once RA has been loaded
+    pub fn load_b(mut self, edge: Option<Edge>) -> Self {
+        self.load_b = edge;
+        self
+    }
+
+    /// ETRGEDG / ABETRG: edge restarting the count
+    pub fn trigger(mut self, edge: Edge, input: TriggerInput) -> Self {
+        self.trigger = Some((edge, input));
+        self
+    }
+}
+
+fn edge_bits(edge: Option<Edge>) -> u8 {
+    match edge {
+        None => 0,
+        Some(Edge::Rising) => 1,
+        Some(Edge::Falling) => 2,
+        Some(Edge::Both) => 3,
+    }
+}
+
+/// A period and low time measured by a `Config::measure` capture
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Measurement {
+    /// Counter clocks from one falling edge to the next
+    pub period_ticks: u32,
+    /// Counter clocks from the falling edge to the rising one
+    pub low_ticks: u32,
+    /// Counter clock
+    pub clock: Hertz,
+}
+
+impl Measurement {
+    pub fn period(&self) -> MicroSeconds {
+        MicroSeconds(ticks_to_us(self.period_ticks, self.clock))
+    }
+
+    pub fn high_time(&self) -> MicroSeconds {
+        MicroSeconds(ticks_to_us(self.high_ticks(), self.clock))
+    }
+
+    pub fn low_time(&self) -> MicroSeconds {
+        MicroSeconds(ticks_to_us(self.low_ticks, self.clock))
+    }
+
+    pub fn frequency(&self) -> Hertz {
+        Hertz(self.clock.0 / self.period_ticks.max(1))
+    }
+
+    /// High time over period, in parts per thousand
+    pub fn duty_permille(&self) -> u32 {
+        (self.high_ticks() as u64 * 1000 / self.period_ticks.max(1) as u64) as u32
+    }
+
+    fn high_ticks(&self) -> u32 {
+        self.period_ticks.saturating_sub(self.low_ticks)
+    }
+}
+
+fn ticks_to_us(ticks: u32, clock: Hertz) -> u32 {
+    (ticks as u64 * 1_000_000 / clock.0 as u64) as u32
+}
+
+/// Capture interrupt events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// LDRAS: RA was loaded
+    LoadA,
+    /// LDRBS: RB was loaded
+    LoadB,
+    /// LOVRS: a capture overwrote an unread one
+    Overrun,
+    /// COVFS: the counter wrapped
+    Overflow,
+    /// ETRGS: the external trigger restarted the count
+    Trigger,
+}
+
+impl Event {
+    /// IER / IDR / SR bit
+    fn mask(self) -> u32 {
+        match self {
+            Event::Overflow => SR_COVFS,
+            Event::Overrun => SR_LOVRS,
+            Event::LoadA => SR_LDRAS,
+            Event::LoadB => SR_LDRBS,
+            Event::Trigger => SR_ETRGS,
+        }
+    }
+}
+
+const SR_COVFS: u32 = 1 << 0;
+const SR_LOVRS: u32 = 1 << 1;
+const SR_LDRAS: u32 = 1 << 5;
+const SR_LDRBS: u32 = 1 << 6;
+const SR_ETRGS: u32 = 1 << 7;
+
+const LATCHED: u32 = 0xff;
+
+/// SR flags cleared by reading SR, one latch per channel so waiting on one
+/// capture register doesn't drop the other's flag
+static STATUS: [Latch; 9] = [
+    Latch::new(LATCHED), Latch::new(LATCHED), Latch::new(LATCHED),
+    Latch::new(LATCHED), Latch::new(LATCHED), Latch::new(LATCHED),
+    Latch::new(LATCHED), Latch::new(LATCHED), Latch::new(LATCHED),
+];
+
+/// A TC channel capturing edges on `PINS`, `(TIOA, TIOB)`
+pub struct Capture<TC, PINS> {
+    tc: TC,
+    pins: PINS,
+    clock: Hertz,
+}
+
+macro_rules! tc_capture {
+    ($TimerCounterX:ident, $Tc:ident, $index:expr, $ccr:ident, $cmr:ident, $ra:ident, $rb:ident, $cv:ident, $sr:ident, $ier:ident, $idr:ident) => {
+        impl<TIOA, TIOB> Capture<$TimerCounterX, (TIOA, TIOB)>
+        where
+            TIOA: TioaPin<$TimerCounterX>,
+            TIOB: TiobPin<$TimerCounterX>,
+        {
+            /// Sets the channel up for capture and starts counting
+            pub fn new(tc: $TimerCounterX, pins: (TIOA, TIOB), config: &Config, pmc: &mut Pmc) -> Self {
+                pmc.enable_clock(PeripheralClock::$Tc);
+                tc.regs().wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+                tc.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+
+                let (etrgedg, abetrg) = match config.trigger {
+                    None => (0, false),
+                    Some((edge, input)) => (edge_bits(Some(edge)), input == TriggerInput::Tioa),
+                };
+                tc.regs().$cmr().write(|w| {
+                    w.tcclks().timer_clock1()
+                        .wave().clear_bit()
+                        .etrgedg().bits(etrgedg)
+                        .abetrg().bit(abetrg)
+                        .ldra().bits(edge_bits(config.load_a))
+                        .ldrb().bits(edge_bits(config.load_b))
+                });
+
+                tc.regs().$sr.read();
+                STATUS[$index].clear();
+                tc.regs().$ccr.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+
+                let clock = Hertz(pmc.clocks.master_clk().0 / 2);
+                Capture { tc, pins, clock }
+            }
+
+            fn take_status(&mut self, mask: u32) -> u32 {
+                STATUS[$index].take(self.tc.regs().$sr.read().bits(), mask)
+            }
+
+            fn take_overrun(&mut self) -> nb::Result<(), Error> {
+                match self.take_status(SR_LOVRS) {
+                    0 => Ok(()),
+                    _ => Err(nb::Error::Other(Error::Overrun)),
+                }
+            }
+
+            /// The counter clock captures are counted in
+            pub fn clock(&self) -> Hertz {
+                self.clock
+            }
+
+            pub fn counter(&self) -> u32 {
+                self.tc.regs().$cv.read().cv().bits()
+            }
+
+            /// The next RA capture
+            pub fn read_a(&mut self) -> nb::Result<u32, Error> {
+                self.take_overrun()?;
+                match self.take_status(SR_LDRAS) {
+                    0 => Err(nb::Error::WouldBlock),
+                    _ => Ok(self.tc.regs().$ra.read().ra().bits()),
+                }
+            }
+
+            /// The next RB capture
+            pub fn read_b(&mut self) -> nb::Result<u32, Error> {
+                self.take_overrun()?;
+                match self.take_status(SR_LDRBS) {
+                    0 => Err(nb::Error::WouldBlock),
+                    _ => Ok(self.tc.regs().$rb.read().rb().bits()),
+                }
+            }
+
+            /// The next period and low time, with the `measure` config.  A
+            /// read later than the following rising edge sees RA from the next
+            /// period, which shows as an overrun when it outlasts the period.
+            pub fn measurement(&mut self) -> nb::Result<Measurement, Error> {
+                self.take_overrun()?;
+                if self.take_status(SR_LDRBS) == 0 {
+                    return Err(nb::Error::WouldBlock);
+                }
+
+                self.take_status(SR_LDRAS);
+                let low_ticks = self.tc.regs().$ra.read().ra().bits();
+                let period_ticks = self.tc.regs().$rb.read().rb().bits();
+                if low_ticks > period_ticks {
+                    return Err(nb::Error::Other(Error::Overrun));
+                }
+
+                Ok(Measurement { period_ticks, low_ticks, clock: self.clock })
+            }
+
+            /// Whether the counter wrapped since the last call, making the
+            /// next capture meaningless
+            pub fn take_overflow(&mut self) -> bool {
+                self.take_status(SR_COVFS) != 0
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                self.tc.regs().$ier.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+            }
+
+            /// Stops listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                self.tc.regs().$idr.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+            }
+
+            /// Stops the channel and releases it along with its pins
+            pub fn free(self) -> ($TimerCounterX, (TIOA, TIOB)) {
+                self.tc.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+                (self.tc, self.pins)
+            }
+        }
+    };
+}
+
+tc_capture!(TimerCounter0, Tc0, 0, ccr0, cmr0, ra0, rb0, cv0, sr0, ier0, idr0);
+tc_capture!(TimerCounter1, Tc1, 1, ccr1, cmr1, ra1, rb1, cv1, sr1, ier1, idr1);
+tc_capture!(TimerCounter2, Tc2, 2, ccr2, cmr2, ra2, rb2, cv2, sr2, ier2, idr2);
+tc_capture!(TimerCounter3, Tc3, 3, ccr0, cmr0, ra0, rb0, cv0, sr0, ier0, idr0);
+tc_capture!(TimerCounter4, Tc4, 4, ccr1, cmr1, ra1, rb1, cv1, sr1, ier1, idr1);
+tc_capture!(TimerCounter5, Tc5, 5, ccr2, cmr2, ra2, rb2, cv2, sr2, ier2, idr2);
+tc_capture!(TimerCounter6, Tc6, 6, ccr0, cmr0, ra0, rb0, cv0, sr0, ier0, idr0);
+tc_capture!(TimerCounter7, Tc7, 7, ccr1, cmr1, ra1, rb1, cv1, sr1, ier1, idr1);
+tc_capture!(TimerCounter8, Tc8, 8, ccr2, cmr2, ra2, rb2, cv2, sr2, ier2, idr2);
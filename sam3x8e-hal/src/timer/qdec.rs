@@ -0,0 +1,331 @@
+/*
+ *    This file (src/timer/qdec.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! TC quadrature decoder
+//!
+//! A whole TC block decodes an encoder with its phases on TIOA and TIOB of
+//! the first channel and its index on TIOB of the second.  The first channel
+//! counts edges up or down with the direction and is cleared on each index
+//! pulse, the second counts index pulses, and the third, when measuring
+//! speed, sets the time base the edges are counted over.  The time base then
+//! restarts the first channel's count, so position and speed are exclusive:
+//!
+//! ```ignore
+//! let pins = (pb25.into_peripheral_b(&mut absr), pb27.into_peripheral_b(&mut absr), pa3.into_peripheral_a(&mut absr));
+//! let config = qdec::Config::new().filter(10).speed(100.hz());
+//! let encoder = QuadratureDecoder::new(p.TC0, pins, &config, &mut pmc);
+//! let rpm = encoder.speed().unwrap() * 60 / (4 * LINES);
+//! ```
+//!
+//! Only TC0 and TC2 have their TIO lines on SAM3X8E pins.
+
+use crate::delay::{TimerCounter0, TimerCounter1, TimerCounter6, TimerCounter7};
+use crate::latch::Latch;
+use crate::pac::{TC0, TC2};
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::time::Hertz;
+
+use super::tc::{clock_for, counter_clock};
+use super::waveform::{TioaPin, TiobPin};
+
+/// Edges counted.  EDGPHA
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edges {
+    /// Both edges of both phases, four counts per encoder line
+    Both,
+    /// Both edges of PHA only, two counts per encoder line
+    PhaseA,
+}
+
+/// Rotation direction.  DIR
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    /// PHA leads PHB, counting up
+    Forward,
+    /// PHB leads PHA, counting down
+    Reverse,
+}
+
+/// Quadrature decoder configuration
+pub struct Config {
+    edges: Edges,
+    invert_a: bool,
+    invert_b: bool,
+    invert_index: bool,
+    swap: bool,
+    index_on_phb: bool,
+    filter: Option<u8>,
+    speed: Option<Hertz>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            edges: Edges::Both,
+            invert_a: false,
+            invert_b: false,
+            invert_index: false,
+            swap: false,
+            index_on_phb: false,
+            filter: None,
+            speed: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn edges(mut self, edges: Edges) -> Self {
+        self.edges = edges;
+        self
+    }
+
+    /// INVA / INVB: inverts the phases before decoding
+    pub fn invert(mut self, a: bool, b: bool) -> Self {
+        self.invert_a = a;
+        self.invert_b = b;
+        self
+    }
+
+    /// INVIDX: inverts the index before decoding
+    pub fn invert_index(mut self, invert: bool) -> Self {
+        self.invert_index = invert;
+        self
+    }
+
+    /// SWAP: swaps PHA and PHB, reversing the direction
+    pub fn swap(mut self, swap: bool) -> Self {
+        self.swap = swap;
+        self
+    }
+
+    /// IDXPHB: takes the index from TIOB of the first channel, for encoders
+    /// with a single phase and an index
+    pub fn index_on_phb(mut self, index_on_phb: bool) -> Self {
+        self.index_on_phb = index_on_phb;
+        self
+    }
+
+    /// FILTER / MAXFILT: ignores pulses on the phases and index shorter than
+    /// `cycles` master clock cycles, 2 to 64
+    pub fn filter(mut self, cycles: u8) -> Self {
+        assert!((2..=64).contains(&cycles));
+        self.filter = Some(cycles);
+        self
+    }
+
+    /// SPEEDEN: counts edges over periods of `rate` for `speed`, using the
+    /// block's third channel as the time base.  Takes the place of `position`.
+    pub fn speed<T: Into<Hertz>>(mut self, rate: T) -> Self {
+        self.speed = Some(rate.into());
+        self
+    }
+}
+
+/// Quadrature decoder interrupt events, raised on the block's first channel
+/// interrupt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// IDX: an index pulse was seen
+    Index,
+    /// DIRCHG: the direction changed
+    DirectionChange,
+    /// QERR: both phases changed at once, counts were lost
+    Error,
+}
+
+impl Event {
+    /// QIER / QIDR / QISR bit
+    fn mask(self) -> u32 {
+        match self {
+            Event::Index => QISR_IDX,
+            Event::DirectionChange => QISR_DIRCHG,
+            Event::Error => QISR_QERR,
+        }
+    }
+}
+
+const QISR_IDX: u32 = 1 << 0;
+const QISR_DIRCHG: u32 = 1 << 1;
+const QISR_QERR: u32 = 1 << 2;
+const QISR_DIR: u32 = 1 << 8;
+
+const LATCHED: u32 = QISR_IDX | QISR_DIRCHG | QISR_QERR;
+
+/// QISR flags cleared by reading QISR, one latch per TC block
+static STATUS: [Latch; 3] = [Latch::new(LATCHED), Latch::new(LATCHED), Latch::new(LATCHED)];
+
+/// A TC block decoding an encoder on `PINS`, `(PHA, PHB, IDX)` with `()` for
+/// an unused index
+pub struct QuadratureDecoder<TC, PINS> {
+    tc: TC,
+    pins: PINS,
+    speed: Option<Hertz>,
+}
+
+macro_rules! tc_qdec {
+    ($TCX:ident, $index:expr, $TimerCounterA:ident, $TimerCounterB:ident, $TcA:ident, $TcB:ident, $TcC:ident) => {
+        impl<PHA, PHB, IDX> QuadratureDecoder<$TCX, (PHA, PHB, IDX)>
+        where
+            PHA: TioaPin<$TimerCounterA>,
+            PHB: TiobPin<$TimerCounterA>,
+            IDX: TiobPin<$TimerCounterB>,
+        {
+            /// Sets the block up for decoding and starts counting
+            pub fn new(tc: $TCX, pins: (PHA, PHB, IDX), config: &Config, pmc: &mut Pmc) -> Self {
+                assert!(PHA::USED && PHB::USED, "no encoder phase pins");
+                pmc.enable_clock(PeripheralClock::$TcA);
+                pmc.enable_clock(PeripheralClock::$TcB);
+                tc.wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+                tc.bmr.write(|w| {
+                    w.qden().set_bit()
+                        .posen().set_bit()
+                        .speeden().bit(config.speed.is_some())
+                        .edgpha().bit(config.edges == Edges::PhaseA)
+                        .inva().bit(config.invert_a)
+                        .invb().bit(config.invert_b)
+                        .invidx().bit(config.invert_index)
+                        .swap().bit(config.swap)
+                        .idxphb().bit(config.index_on_phb)
+                        .filter().bit(config.filter.is_some())
+                });
+                if let Some(cycles) = config.filter {
+                    tc.bmr.modify(|_, w| unsafe { w.maxfilt().bits(cycles - 1) });
+                }
+
+                // Position on the first channel, revolutions on the second,
+                // both counting decoded edges
+                tc.cmr1().write(|w| w.tcclks().xc0());
+                match config.speed {
+                    None => tc.cmr0().write(|w| w.tcclks().xc0()),
+                    // The time base comes in on TIOA, each rising edge loads
+                    // RA with the edge count and restarts it
+                    Some(_) => tc.cmr0().write(|w| {
+                        w.tcclks().xc0()
+                            .abetrg().set_bit()
+                            .etrgedg().rising()
+                            .ldra().rising()
+                    }),
+                }
+
+                if let Some(rate) = config.speed {
+                    pmc.enable_clock(PeripheralClock::$TcC);
+
+                    // TIOA2 toggles on RC compares, rising once per period
+                    let (tcclks, rc) = clock_for(pmc.clocks.master_clk().0, Hertz(rate.0 * 2));
+                    tc.cmr2_wave_eq_1().write(|w| {
+                        w.tcclks().bits(tcclks).wave().set_bit().wavsel().up_rc().acpc().toggle()
+                    });
+                    tc.rc2.write(|w| unsafe { w.rc().bits(rc) });
+                    tc.ccr2.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                }
+
+                tc.qisr.read();
+                STATUS[$index].clear();
+                tc.ccr0.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                tc.ccr1.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+
+                // The time base actually runs at a whole number of counter
+                // clocks, `speed` scales by what it got
+                let speed = config.speed.map(|_| {
+                    let tcclks = tc.cmr2_wave_eq_1().read().tcclks().bits();
+                    let clock = counter_clock(pmc.clocks.master_clk().0, tcclks);
+                    Hertz(clock / (2 * (tc.rc2.read().rc().bits() + 1)))
+                });
+                QuadratureDecoder { tc, pins, speed }
+            }
+
+            fn status(&self) -> u32 {
+                STATUS[$index].update(self.tc.qisr.read().bits())
+            }
+
+            fn take_status(&mut self, mask: u32) -> u32 {
+                STATUS[$index].take(self.tc.qisr.read().bits(), mask)
+            }
+
+            /// Edges counted since the last index pulse, negative going
+            /// backwards.  `None` with `Config::speed`, where the count
+            /// restarts on every time base period.
+            pub fn position(&self) -> Option<i32> {
+                match self.speed {
+                    None => Some(self.tc.cv0.read().cv().bits() as i32),
+                    Some(_) => None,
+                }
+            }
+
+            /// Index pulses counted, negative going backwards
+            pub fn revolutions(&self) -> i32 {
+                self.tc.cv1.read().cv().bits() as i32
+            }
+
+            /// Edges per second over the last time base period, negative
+            /// going backwards.  `None` without `Config::speed`.
+            pub fn speed(&self) -> Option<i32> {
+                self.speed.map(|rate| self.tc.ra0.read().ra().bits() as i32 * rate.0 as i32)
+            }
+
+            /// The time base's actual rate
+            pub fn speed_rate(&self) -> Option<Hertz> {
+                self.speed
+            }
+
+            pub fn direction(&self) -> Direction {
+                match self.status() & QISR_DIR {
+                    0 => Direction::Forward,
+                    _ => Direction::Reverse,
+                }
+            }
+
+            /// Restarts the position and revolution counts at zero
+            pub fn reset(&mut self) {
+                self.tc.ccr0.write_with_zero(|w| w.swtrg().set_bit());
+                self.tc.ccr1.write_with_zero(|w| w.swtrg().set_bit());
+            }
+
+            /// Starts listening for an interrupt event
+            pub fn listen(&mut self, event: Event) {
+                self.tc.qier.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+            }
+
+            /// Stops listening for an interrupt event
+            pub fn unlisten(&mut self, event: Event) {
+                self.tc.qidr.write_with_zero(|w| unsafe { w.bits(event.mask()) });
+            }
+
+            /// Whether `event` happened since the last call
+            pub fn take_event(&mut self, event: Event) -> bool {
+                self.take_status(event.mask()) != 0
+            }
+
+            /// Stops decoding and releases the block along with its pins
+            pub fn free(self) -> ($TCX, (PHA, PHB, IDX)) {
+                self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                self.tc.ccr1.write_with_zero(|w| w.clkdis().set_bit());
+                self.tc.ccr2.write_with_zero(|w| w.clkdis().set_bit());
+                self.tc.bmr.reset();
+                (self.tc, self.pins)
+            }
+        }
+    };
+}
+
+tc_qdec!(TC0, 0, TimerCounter0, TimerCounter1, Tc0, Tc1, Tc2);
+tc_qdec!(TC2, 2, TimerCounter6, TimerCounter7, Tc6, Tc7, Tc8);
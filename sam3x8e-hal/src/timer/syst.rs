@@ -31,7 +31,7 @@ impl Timer<SYST> {
         T: Into<Hertz>,
     {
         syst.set_clock_source(SystClkSource::Core);
-        let mut timer = Timer { tim: syst };
+        let mut timer = Timer { tim: syst, clock: 84.mhz().into() };
         timer.start(timeout);
         timer
     }
@@ -54,8 +54,7 @@ impl CountDown for Timer<SYST> {
     where
         T: Into<Hertz>,
     {
-        let clock_speed : Hertz = 84.mhz().into();
-        let reload_value = (clock_speed.0 / timeout.into().0) - 1;
+        let reload_value = (self.clock.0 / timeout.into().0) - 1;
         assert!(reload_value < (1 << 24));
 
         self.tim.set_reload(reload_value);
@@ -0,0 +1,179 @@
+/*
+ *    This file (src/timer/tc.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Timer Counter channels as count down timers
+//!
+//! Each of the nine channels counts up to RC and restarts, raising CPCS on
+//! every compare.  Channels of one TC block are handed out by `split`, and
+//! `join` puts the block back together once all three are returned:
+//!
+//! ```ignore
+//! let (tc0, tc1, _) = p.TC0.split();
+//! let mut tick = Timer::tc(tc0, 1.khz(), &mut pmc);
+//! let mut blink = Timer::tc(tc1, 2.hz(), &mut pmc);
+//! tick.listen();
+//! unsafe { NVIC::unmask(Timer::<TimerCounter0>::INTERRUPT) };
+//! ```
+
+use crate::delay::{
+    TimerCounter0, TimerCounter1, TimerCounter2, TimerCounter3, TimerCounter4, TimerCounter5, TimerCounter6,
+    TimerCounter7, TimerCounter8,
+};
+use crate::hal::timer::{CountDown, Periodic};
+use crate::pac::{Interrupt, Peripherals, TC0, TC1, TC2};
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::time::Hertz;
+use void::Void;
+
+use super::Timer;
+
+/// TIMER_CLOCK5
+const SLOW_CLOCK: u32 = 32_768;
+
+/// TCCLKS and RC for counting at `frequency`.  TIMER_CLOCK1 to 4 divide the
+/// master clock by 2, 8, 32 and 128, the fastest one that fits the 32-bit
+/// counter is used, falling back on the slow clock.
+pub(crate) fn clock_for(master_clk: u32, frequency: Hertz) -> (u8, u32) {
+    assert!(frequency.0 > 0);
+    let frequency = frequency.0 as u64;
+
+    [(0, master_clk / 2), (1, master_clk / 8), (2, master_clk / 32), (3, master_clk / 128), (4, SLOW_CLOCK)]
+        .iter()
+        .map(|&(tcclks, clock)| (tcclks, (clock as u64 + frequency / 2) / frequency))
+        .find(|&(_, ticks)| ticks <= 1 << 32)
+        .map(|(tcclks, ticks)| (tcclks, ticks.max(2) as u32 - 1))
+        .expect("timer period too long")
+}
+
+/// Counter clock frequency for a TCCLKS value
+pub(crate) fn counter_clock(master_clk: u32, tcclks: u8) -> u32 {
+    match tcclks {
+        0 => master_clk / 2,
+        1 => master_clk / 8,
+        2 => master_clk / 32,
+        3 => master_clk / 128,
+        _ => SLOW_CLOCK,
+    }
+}
+
+/// Splits a TC block into its three channels
+pub trait TimerCounterExt: Sized {
+    type Channels;
+
+    fn split(self) -> Self::Channels;
+
+    /// Rebuilds the block from its three channels
+    fn join(channels: Self::Channels) -> Self;
+}
+
+macro_rules! tc_block {
+    ($TCX:ident, $TimerCounterA:ident, $TimerCounterB:ident, $TimerCounterC:ident) => {
+        impl TimerCounterExt for $TCX {
+            type Channels = ($TimerCounterA, $TimerCounterB, $TimerCounterC);
+
+            fn split(self) -> Self::Channels {
+                // The block is gone until all three channels come back
+                unsafe { ($TimerCounterA::steal(), $TimerCounterB::steal(), $TimerCounterC::steal()) }
+            }
+
+            fn join(_channels: Self::Channels) -> Self {
+                unsafe { Peripherals::steal().$TCX }
+            }
+        }
+    };
+}
+
+tc_block!(TC0, TimerCounter0, TimerCounter1, TimerCounter2);
+tc_block!(TC1, TimerCounter3, TimerCounter4, TimerCounter5);
+tc_block!(TC2, TimerCounter6, TimerCounter7, TimerCounter8);
+
+macro_rules! tc_timer {
+    ($TimerCounterX:ident, $Tc:ident, $INTERRUPT:ident, $ccr:ident, $cmr:ident, $rc:ident, $sr:ident, $ier:ident, $idr:ident) => {
+        impl Timer<$TimerCounterX> {
+            /// Interrupt raised on each period once listening
+            pub const INTERRUPT: Interrupt = Interrupt::$INTERRUPT;
+
+            /// Configures a TC channel as a periodic count down timer
+            pub fn tc<T>(tc: $TimerCounterX, timeout: T, pmc: &mut Pmc) -> Self
+            where
+                T: Into<Hertz>,
+            {
+                pmc.enable_clock(PeripheralClock::$Tc);
+                tc.regs().wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+
+                let mut timer = Timer { tim: tc, clock: pmc.clocks.master_clk() };
+                timer.start(timeout);
+                timer
+            }
+
+            /// Starts listening, CPCS is cleared by `wait`
+            pub fn listen(&mut self) {
+                self.tim.regs().$ier.write_with_zero(|w| w.cpcs().set_bit());
+            }
+
+            /// Stops listening
+            pub fn unlisten(&mut self) {
+                self.tim.regs().$idr.write_with_zero(|w| w.cpcs().set_bit());
+            }
+
+            /// Stops the channel and releases it
+            pub fn free(self) -> $TimerCounterX {
+                self.tim.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+                self.tim
+            }
+        }
+
+        impl CountDown for Timer<$TimerCounterX> {
+            type Time = Hertz;
+
+            fn start<T>(&mut self, timeout: T)
+            where
+                T: Into<Hertz>,
+            {
+                let (tcclks, rc) = clock_for(self.clock.0, timeout.into());
+                let tc = self.tim.regs();
+
+                tc.$ccr.write_with_zero(|w| w.clkdis().set_bit());
+                tc.$cmr().write(|w| w.tcclks().bits(tcclks).wave().set_bit().wavsel().up_rc());
+                tc.$rc.write(|w| unsafe { w.rc().bits(rc) });
+
+                // Reading SR drops a compare left over from the last period
+                tc.$sr.read();
+                tc.$ccr.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+            }
+
+            fn wait(&mut self) -> nb::Result<(), Void> {
+                match self.tim.regs().$sr.read().cpcs().bit_is_set() {
+                    true => Ok(()),
+                    false => Err(nb::Error::WouldBlock),
+                }
+            }
+        }
+
+        impl Periodic for Timer<$TimerCounterX> {}
+    };
+}
+
+tc_timer!(TimerCounter0, Tc0, TC0, ccr0, cmr0_wave_eq_1, rc0, sr0, ier0, idr0);
+tc_timer!(TimerCounter1, Tc1, TC1, ccr1, cmr1_wave_eq_1, rc1, sr1, ier1, idr1);
+tc_timer!(TimerCounter2, Tc2, TC2, ccr2, cmr2_wave_eq_1, rc2, sr2, ier2, idr2);
+tc_timer!(TimerCounter3, Tc3, TC3, ccr0, cmr0_wave_eq_1, rc0, sr0, ier0, idr0);
+tc_timer!(TimerCounter4, Tc4, TC4, ccr1, cmr1_wave_eq_1, rc1, sr1, ier1, idr1);
+tc_timer!(TimerCounter5, Tc5, TC5, ccr2, cmr2_wave_eq_1, rc2, sr2, ier2, idr2);
+tc_timer!(TimerCounter6, Tc6, TC6, ccr0, cmr0_wave_eq_1, rc0, sr0, ier0, idr0);
+tc_timer!(TimerCounter7, Tc7, TC7, ccr1, cmr1_wave_eq_1, rc1, sr1, ier1, idr1);
+tc_timer!(TimerCounter8, Tc8, TC8, ccr2, cmr2_wave_eq_1, rc2, sr2, ier2, idr2);
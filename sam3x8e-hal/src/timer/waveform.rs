@@ -0,0 +1,523 @@
+/*
+ *    This file (src/timer/waveform.rs) is part of sam3x8e-hal.
+ *
+ *    sam3x8e-hal is free software: you can redistribute it and/or modify
+ *    it under the terms of the GNU Lesser General Public License as published
+ *    by the Free Software Foundation, either version 3 of the License, or
+ *    (at your option) any later version.
+ *
+ *    sam3x8e-hal is distributed in the hope that it will be useful,
+ *    but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *    GNU Lesser General Public License for more details.
+ *
+ *    You should have received a copy of the GNU Lesser General Public License
+ *    along with sam3x8e-hal.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! TC waveform generation on TIOA and TIOB
+//!
+//! The counter runs up to RC and restarts, or up to RC and back down.  The RA
+//! and RB compares, the RC compare, external events and software triggers each
+//! set, clear or toggle TIOA and TIOB, so RC sets the period and RA and RB the
+//! duty cycles of two outputs.  The default effects give PWM with a duty of
+//! RA / RC on TIOA and RB / RC on TIOB, held low at 0 and high at RC:
+//!
+//! ```ignore
+//! let pins = (piob.pb25.into_peripheral_b(&mut piob.absr), ());
+//! let mut fan = Waveform::new(tc0, pins, 25.khz(), &waveform::Config::new(), &mut pmc);
+//! fan.set_duty(Tio::A, fan.period() / 2);
+//! fan.enable(Tio::A);
+//! ```
+//!
+//! TC1's TIOA3 to TIOB5 are on PIOE, which the SAM3X8E doesn't have, so its
+//! channels can only run without pins.
+
+use crate::delay::{
+    TimerCounter0, TimerCounter1, TimerCounter2, TimerCounter3, TimerCounter4, TimerCounter5, TimerCounter6,
+    TimerCounter7, TimerCounter8,
+};
+use crate::gpio::pioa::{PA2, PA3, PA5, PA6};
+use crate::gpio::piob::{PB25, PB27};
+use crate::gpio::pioc::{PC25, PC26, PC28, PC29};
+use crate::gpio::piod::{PD7, PD8};
+use crate::gpio::{PeripheralA, PeripheralB};
+#[cfg(feature = "unproven")]
+use crate::hal;
+use crate::pmc::{PeripheralClock, Pmc};
+use crate::time::Hertz;
+
+use super::tc::{clock_for, counter_clock};
+
+/// TC channel outputs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Tio {
+    A,
+    B,
+}
+
+/// Counting.  WAVSEL
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Counting {
+    /// Up to RC then back to 0, a period is RC + 1 counter clocks
+    Up,
+    /// Up to RC then back down, a period is 2 * RC counter clocks
+    UpDown,
+}
+
+/// What an event does to an output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    None,
+    Set,
+    Clear,
+    Toggle,
+}
+
+/// Effects of the events on an output.  ACPA / ACPC / AEEVT / ASWTRG for
+/// TIOA, BCPB / BCPC / BEEVT / BSWTRG for TIOB
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Effects {
+    /// RA compare for TIOA, RB compare for TIOB
+    pub compare: Effect,
+    /// RC compare
+    pub period: Effect,
+    /// External event, see `Config::trigger`
+    pub external: Effect,
+    /// Software trigger, which also starts the counter
+    pub software: Effect,
+}
+
+impl Effects {
+    /// PWM with a duty of RA / RC or RB / RC, high first
+    pub fn pwm(counting: Counting) -> Self {
+        match counting {
+            Counting::Up => Effects {
+                compare: Effect::Clear,
+                period: Effect::Set,
+                external: Effect::Set,
+                software: Effect::Set,
+            },
+            // High around the bottom of the count, symmetric.  The RC compare
+            // at the top keeps the toggles in step.
+            Counting::UpDown => Effects {
+                compare: Effect::Toggle,
+                period: Effect::Clear,
+                external: Effect::Set,
+                software: Effect::Set,
+            },
+        }
+    }
+
+    /// Output held low
+    fn idle() -> Self {
+        Effects {
+            compare: Effect::Clear,
+            period: Effect::Clear,
+            external: Effect::Clear,
+            software: Effect::Clear,
+        }
+    }
+
+    /// Output held high
+    fn held_high() -> Self {
+        Effects {
+            compare: Effect::Set,
+            period: Effect::Set,
+            external: Effect::Set,
+            software: Effect::Set,
+        }
+    }
+
+    /// CMR bits 23:16 for TIOA, 31:24 for TIOB
+    fn bits(self, tio: Tio) -> u32 {
+        let effect = |effect: Effect| effect as u32;
+        let bits = effect(self.compare)
+            | effect(self.period) << 2
+            | effect(self.external) << 4
+            | effect(self.software) << 6;
+
+        match tio {
+            Tio::A => bits << 16,
+            Tio::B => bits << 24,
+        }
+    }
+}
+
+/// Edge of TIOB that makes an external event
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Waveform configuration
+pub struct Config {
+    counting: Counting,
+    tioa: Option<Effects>,
+    tiob: Option<Effects>,
+    trigger: Option<Edge>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            counting: Counting::Up,
+            tioa: None,
+            tiob: None,
+            trigger: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {..Self::default()}
+    }
+
+    pub fn counting(mut self, counting: Counting) -> Self {
+        self.counting = counting;
+        self
+    }
+
+    /// Effects on TIOA, PWM for `counting` if not set
+    pub fn tioa(mut self, effects: Effects) -> Self {
+        self.tioa = Some(effects);
+        self
+    }
+
+    /// Effects on TIOB, PWM for `counting` if not set
+    pub fn tiob(mut self, effects: Effects) -> Self {
+        self.tiob = Some(effects);
+        self
+    }
+
+    /// Restarts the count on an `edge` of TIOB, synchronising the waveform to
+    /// an outside signal.  TIOB is then an input, not an output.
+    pub fn trigger(mut self, edge: Edge) -> Self {
+        self.trigger = Some(edge);
+        self
+    }
+}
+
+/// TIOA pin
+pub trait TioaPin<TC> {
+    const USED: bool;
+}
+
+/// TIOB pin
+pub trait TiobPin<TC> {
+    const USED: bool;
+}
+
+macro_rules! tio_pins {
+    ($($TimerCounterX:ident: ($($TIOA:ty),*), ($($TIOB:ty),*);)+) => {
+        $(
+            impl TioaPin<$TimerCounterX> for () {
+                const USED: bool = false;
+            }
+
+            impl TiobPin<$TimerCounterX> for () {
+                const USED: bool = false;
+            }
+
+            $(
+                impl TioaPin<$TimerCounterX> for $TIOA {
+                    const USED: bool = true;
+                }
+            )*
+
+            $(
+                impl TiobPin<$TimerCounterX> for $TIOB {
+                    const USED: bool = true;
+                }
+            )*
+        )+
+    };
+}
+
+tio_pins! {
+    TimerCounter0: (PB25<PeripheralB>), (PB27<PeripheralB>);
+    TimerCounter1: (PA2<PeripheralA>), (PA3<PeripheralA>);
+    TimerCounter2: (PA5<PeripheralA>), (PA6<PeripheralA>);
+    TimerCounter3: (), ();
+    TimerCounter4: (), ();
+    TimerCounter5: (), ();
+    TimerCounter6: (PC25<PeripheralB>), (PC26<PeripheralB>);
+    TimerCounter7: (PC28<PeripheralB>), (PC29<PeripheralB>);
+    TimerCounter8: (PD7<PeripheralB>), (PD8<PeripheralB>);
+}
+
+/// A TC channel generating waveforms on `PINS`, `(TIOA, TIOB)` with `()` for
+/// an output that isn't used
+pub struct Waveform<TC, PINS> {
+    tc: TC,
+    pins: PINS,
+    master_clk: u32,
+    counting: Counting,
+    tioa: Effects,
+    tiob: Effects,
+    /// Whether TIOA and TIOB are driven by their effects
+    enabled: (bool, bool),
+}
+
+macro_rules! tc_waveform {
+    ($TimerCounterX:ident, $Tc:ident, $ccr:ident, $cmr:ident, $ra:ident, $rb:ident, $rc:ident) => {
+        impl<TIOA, TIOB> Waveform<$TimerCounterX, (TIOA, TIOB)>
+        where
+            TIOA: TioaPin<$TimerCounterX>,
+            TIOB: TiobPin<$TimerCounterX>,
+        {
+            /// Sets the channel up with a period of `frequency` and starts
+            /// counting, both outputs held low until enabled
+            pub fn new<F>(tc: $TimerCounterX, pins: (TIOA, TIOB), frequency: F, config: &Config, pmc: &mut Pmc) -> Self
+            where
+                F: Into<Hertz>,
+            {
+                assert!(!(config.trigger.is_some() && TIOB::USED), "TIOB is the trigger input");
+                pmc.enable_clock(PeripheralClock::$Tc);
+                tc.regs().wpmr.write(|w| w.wpen().clear_bit().wpkey().passwd());
+                tc.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+
+                let (edge, enetrg) = match config.trigger {
+                    None => (0, false),
+                    Some(Edge::Rising) => (1, true),
+                    Some(Edge::Falling) => (2, true),
+                    Some(Edge::Both) => (3, true),
+                };
+                tc.regs().$cmr().write(|w| {
+                    let w = w.wave().set_bit().eevtedg().bits(edge).enetrg().bit(enetrg);
+                    // With TIOB as the event source it's an input
+                    let w = match config.trigger {
+                        Some(_) => w.eevt().tiob(),
+                        None => w.eevt().xc0(),
+                    };
+                    match config.counting {
+                        Counting::Up => w.wavsel().up_rc(),
+                        Counting::UpDown => w.wavsel().updown_rc(),
+                    }
+                });
+
+                let mut waveform = Waveform {
+                    tc,
+                    pins,
+                    master_clk: pmc.clocks.master_clk().0,
+                    counting: config.counting,
+                    tioa: config.tioa.unwrap_or(Effects::pwm(config.counting)),
+                    tiob: config.tiob.unwrap_or(Effects::pwm(config.counting)),
+                    enabled: (false, false),
+                };
+                waveform.disable(Tio::A);
+                waveform.disable(Tio::B);
+                waveform.set_frequency(frequency);
+                waveform.start();
+                waveform
+            }
+
+            /// Restarts counting from the start of a period, after `stop`
+            pub fn start(&mut self) {
+                self.tc.regs().$ccr.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+            }
+
+            /// Stops counting, the outputs keep their level
+            pub fn stop(&mut self) {
+                self.tc.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+            }
+
+            /// Lets the effects drive `tio`
+            pub fn enable(&mut self, tio: Tio) {
+                match tio {
+                    Tio::A => self.enabled.0 = true,
+                    Tio::B => self.enabled.1 = true,
+                }
+                self.update_effects(tio);
+            }
+
+            /// Holds `tio` low, from the next compare on
+            pub fn disable(&mut self, tio: Tio) {
+                match tio {
+                    Tio::A => self.enabled.0 = false,
+                    Tio::B => self.enabled.1 = false,
+                }
+                self.update_effects(tio);
+            }
+
+            /// Writes the effects for `tio`'s state and duty cycle.  With PWM
+            /// effects a compare at 0 or RC only fires once per period, so
+            /// those duty cycles hold the output instead.
+            fn update_effects(&mut self, tio: Tio) {
+                let (enabled, effects) = match tio {
+                    Tio::A => (self.enabled.0, self.tioa),
+                    Tio::B => (self.enabled.1, self.tiob),
+                };
+
+                let effects = match (enabled, self.duty(tio)) {
+                    (false, _) => Effects::idle(),
+                    (true, _) if effects != Effects::pwm(self.counting) => effects,
+                    (true, 0) => Effects::idle(),
+                    (true, duty) if duty >= self.period() => Effects::held_high(),
+                    (true, _) => effects,
+                };
+                self.set_effects(tio, effects);
+            }
+
+            fn set_effects(&mut self, tio: Tio, effects: Effects) {
+                let mask = match tio {
+                    Tio::A => 0xff << 16,
+                    Tio::B => 0xff << 24,
+                };
+                let bits = effects.bits(tio);
+                self.tc.regs().$cmr().modify(|r, w| unsafe { w.bits((r.bits() & !mask) | bits) });
+            }
+
+            /// RC, the largest duty cycle.  A period is RC + 1 counter clocks
+            /// counting up, 2 * RC counting up and down.
+            pub fn period(&self) -> u32 {
+                self.tc.regs().$rc.read().rc().bits()
+            }
+
+            pub fn frequency(&self) -> Hertz {
+                let tcclks = self.tc.regs().$cmr().read().tcclks().bits();
+                let clock = counter_clock(self.master_clk, tcclks);
+                let clocks = match self.counting {
+                    Counting::Up => self.period() as u64 + 1,
+                    Counting::UpDown => 2 * self.period().max(1) as u64,
+                };
+                Hertz((clock as u64 / clocks) as u32)
+            }
+
+            /// Changes the period, keeping the duty cycles in proportion
+            pub fn set_frequency<F: Into<Hertz>>(&mut self, frequency: F) {
+                let frequency = frequency.into();
+                // `clock_for` gives RC for an RC + 1 clock period, counting
+                // up and down a half period is RC clocks
+                let (tcclks, rc) = match self.counting {
+                    Counting::Up => clock_for(self.master_clk, frequency),
+                    Counting::UpDown => {
+                        let (tcclks, rc) = clock_for(self.master_clk, Hertz(frequency.0 * 2));
+                        (tcclks, rc.saturating_add(1))
+                    }
+                };
+
+                let old = self.period().max(1) as u64;
+                let scale = |duty: u32| (duty as u64 * rc as u64 / old) as u32;
+                let (ra, rb) = (scale(self.duty(Tio::A)), scale(self.duty(Tio::B)));
+
+                let tc = self.tc.regs();
+                tc.$cmr().modify(|_, w| w.tcclks().bits(tcclks));
+                tc.$rc.write(|w| unsafe { w.rc().bits(rc) });
+                tc.$ra.write(|w| unsafe { w.ra().bits(ra) });
+                tc.$rb.write(|w| unsafe { w.rb().bits(rb) });
+                self.update_effects(Tio::A);
+                self.update_effects(Tio::B);
+            }
+
+            /// RA for TIOA, RB for TIOB
+            pub fn duty(&self, tio: Tio) -> u32 {
+                match tio {
+                    Tio::A => self.tc.regs().$ra.read().ra().bits(),
+                    Tio::B => self.tc.regs().$rb.read().rb().bits(),
+                }
+            }
+
+            /// Duty cycle in counter clocks, up to `period`
+            pub fn set_duty(&mut self, tio: Tio, duty: u32) {
+                assert!(duty <= self.period());
+                match tio {
+                    Tio::A => self.tc.regs().$ra.write(|w| unsafe { w.ra().bits(duty) }),
+                    Tio::B => self.tc.regs().$rb.write(|w| unsafe { w.rb().bits(duty) }),
+                }
+                self.update_effects(tio);
+            }
+
+            /// Stops the channel and releases it along with its pins
+            pub fn free(self) -> ($TimerCounterX, (TIOA, TIOB)) {
+                self.tc.regs().$ccr.write_with_zero(|w| w.clkdis().set_bit());
+                (self.tc, self.pins)
+            }
+        }
+
+        /// TIOA and TIOB as two channels sharing the period
+        #[cfg(feature = "unproven")]
+        impl<TIOA, TIOB> hal::Pwm for Waveform<$TimerCounterX, (TIOA, TIOB)>
+        where
+            TIOA: TioaPin<$TimerCounterX>,
+            TIOB: TiobPin<$TimerCounterX>,
+        {
+            type Channel = Tio;
+            type Time = Hertz;
+            type Duty = u32;
+
+            fn disable(&mut self, tio: Tio) {
+                <Waveform<$TimerCounterX, (TIOA, TIOB)>>::disable(self, tio)
+            }
+
+            fn enable(&mut self, tio: Tio) {
+                <Waveform<$TimerCounterX, (TIOA, TIOB)>>::enable(self, tio)
+            }
+
+            fn get_period(&self) -> Hertz {
+                self.frequency()
+            }
+
+            fn get_duty(&self, tio: Tio) -> u32 {
+                self.duty(tio)
+            }
+
+            fn get_max_duty(&self) -> u32 {
+                self.period()
+            }
+
+            fn set_duty(&mut self, tio: Tio, duty: u32) {
+                <Waveform<$TimerCounterX, (TIOA, TIOB)>>::set_duty(self, tio, duty)
+            }
+
+            fn set_period<P>(&mut self, period: P)
+            where
+                P: Into<Hertz>,
+            {
+                self.set_frequency(period)
+            }
+        }
+
+        /// TIOA on its own
+        #[cfg(feature = "unproven")]
+        impl<TIOA> hal::PwmPin for Waveform<$TimerCounterX, (TIOA, ())>
+        where
+            TIOA: TioaPin<$TimerCounterX>,
+        {
+            type Duty = u32;
+
+            fn disable(&mut self) {
+                <Waveform<$TimerCounterX, (TIOA, ())>>::disable(self, Tio::A)
+            }
+
+            fn enable(&mut self) {
+                <Waveform<$TimerCounterX, (TIOA, ())>>::enable(self, Tio::A)
+            }
+
+            fn get_duty(&self) -> u32 {
+                self.duty(Tio::A)
+            }
+
+            fn get_max_duty(&self) -> u32 {
+                self.period()
+            }
+
+            fn set_duty(&mut self, duty: u32) {
+                <Waveform<$TimerCounterX, (TIOA, ())>>::set_duty(self, Tio::A, duty)
+            }
+        }
+    };
+}
+
+tc_waveform!(TimerCounter0, Tc0, ccr0, cmr0_wave_eq_1, ra0, rb0, rc0);
+tc_waveform!(TimerCounter1, Tc1, ccr1, cmr1_wave_eq_1, ra1, rb1, rc1);
+tc_waveform!(TimerCounter2, Tc2, ccr2, cmr2_wave_eq_1, ra2, rb2, rc2);
+tc_waveform!(TimerCounter3, Tc3, ccr0, cmr0_wave_eq_1, ra0, rb0, rc0);
+tc_waveform!(TimerCounter4, Tc4, ccr1, cmr1_wave_eq_1, ra1, rb1, rc1);
+tc_waveform!(TimerCounter5, Tc5, ccr2, cmr2_wave_eq_1, ra2, rb2, rc2);
+tc_waveform!(TimerCounter6, Tc6, ccr0, cmr0_wave_eq_1, ra0, rb0, rc0);
+tc_waveform!(TimerCounter7, Tc7, ccr1, cmr1_wave_eq_1, ra1, rb1, rc1);
+tc_waveform!(TimerCounter8, Tc8, ccr2, cmr2_wave_eq_1, ra2, rb2, rc2);